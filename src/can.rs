@@ -3,7 +3,7 @@ pub mod control {
     use core::convert::TryFrom;
     use num_enum::{IntoPrimitive, TryFromPrimitive, TryFromPrimitiveError};
 
-    #[derive(Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
+    #[derive(Copy, Clone, Debug, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
     #[repr(u8)]
     pub enum OperationMode {
         NormalCanFD = 0,
@@ -110,7 +110,7 @@ pub mod control {
         }
     }
 
-    #[derive(Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
+    #[derive(Copy, Clone, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
     #[repr(u8)]
     pub enum RetransmissionAttempts {
         Disabled = 0,
@@ -118,7 +118,7 @@ pub mod control {
         UnlimitedRetries = 3,
     }
 
-    #[derive(Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
+    #[derive(Copy, Clone, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
     #[repr(u8)]
     pub enum PayloadSize {
         Bytes8 = 0,
@@ -131,6 +131,22 @@ pub mod control {
         Bytes64 = 7,
     }
 
+    impl PayloadSize {
+        /// Number of payload bytes each object in the FIFO has room for.
+        pub fn bytes(&self) -> usize {
+            match self {
+                PayloadSize::Bytes8 => 8,
+                PayloadSize::Bytes12 => 12,
+                PayloadSize::Bytes16 => 16,
+                PayloadSize::Bytes20 => 20,
+                PayloadSize::Bytes24 => 24,
+                PayloadSize::Bytes32 => 32,
+                PayloadSize::Bytes48 => 48,
+                PayloadSize::Bytes64 => 64,
+            }
+        }
+    }
+
     bitfield! {
         pub struct C1TXQCON(u32);
         impl Debug;
@@ -195,6 +211,43 @@ pub mod control {
         }
     }
 
+    bitfield! {
+        pub struct C1TEFCON(u32);
+        impl Debug;
+        u8;
+        pub tefneie, set_tefneie: 0;
+        pub tefhie, set_tefhie: 1;
+        pub teffie, set_teffie: 2;
+        pub tefovie, set_tefovie: 3;
+        pub teftsen, set_teftsen: 5;
+        pub uinc, set_uinc: 8;
+        pub freset, set_freset: 10;
+        _fsize, _set_fsize: 28, 24;
+    }
+
+    impl C1TEFCON {
+        pub fn fifo_size(&self) -> u8 {
+            self._fsize() + 1
+        }
+
+        /// 1 to 32, anything else is clamped.
+        pub fn set_fifo_size(&mut self, size: u8) {
+            self._set_fsize(size.clamp(1, 32) - 1);
+        }
+    }
+
+    impl Register for C1TEFCON {
+        fn address() -> SFRAddress {
+            SFRAddress::C1TEFCON
+        }
+    }
+
+    impl From<C1TEFCON> for u32 {
+        fn from(reg: C1TEFCON) -> Self {
+            reg.0
+        }
+    }
+
     impl Register for C1TXQCON {
         fn address() -> SFRAddress {
             SFRAddress::C1TXQCON
@@ -213,6 +266,7 @@ pub mod fifo {
 
     use crate::generic::SFRAddress;
 
+    #[derive(Copy, Clone, Eq, PartialEq)]
     pub enum Mode {
         Transmit,
         Receive,
@@ -258,6 +312,14 @@ pub mod fifo {
         pub fifoci, _: 12, 8;
     }
 
+    impl StatusRegister {
+        /// Mask of the flags that are cleared by writing 0 (RXOVIF, TXATIF, TXERR, TXLARB and
+        /// TXABT). The others are read only.
+        pub fn clearable_flags() -> u32 {
+            0b1111_1000
+        }
+    }
+
     impl From<StatusRegister> for u32 {
         fn from(reg: StatusRegister) -> Self {
             reg.0
@@ -349,4 +411,2035 @@ pub mod fifo {
             _ => Err(fifo_number),
         }
     }
+
+    pub fn get_fifo_user_address_address(fifo_number: u8) -> Result<SFRAddress, u8> {
+        match fifo_number {
+            1 => Ok(SFRAddress::C1FIFOUA1),
+            2 => Ok(SFRAddress::C1FIFOUA2),
+            3 => Ok(SFRAddress::C1FIFOUA3),
+            4 => Ok(SFRAddress::C1FIFOUA4),
+            5 => Ok(SFRAddress::C1FIFOUA5),
+            6 => Ok(SFRAddress::C1FIFOUA6),
+            7 => Ok(SFRAddress::C1FIFOUA7),
+            8 => Ok(SFRAddress::C1FIFOUA8),
+            9 => Ok(SFRAddress::C1FIFOUA9),
+            10 => Ok(SFRAddress::C1FIFOUA10),
+            11 => Ok(SFRAddress::C1FIFOUA11),
+            12 => Ok(SFRAddress::C1FIFOUA12),
+            13 => Ok(SFRAddress::C1FIFOUA13),
+            14 => Ok(SFRAddress::C1FIFOUA14),
+            15 => Ok(SFRAddress::C1FIFOUA15),
+            16 => Ok(SFRAddress::C1FIFOUA16),
+            17 => Ok(SFRAddress::C1FIFOUA17),
+            18 => Ok(SFRAddress::C1FIFOUA18),
+            19 => Ok(SFRAddress::C1FIFOUA19),
+            20 => Ok(SFRAddress::C1FIFOUA20),
+            21 => Ok(SFRAddress::C1FIFOUA21),
+            22 => Ok(SFRAddress::C1FIFOUA22),
+            23 => Ok(SFRAddress::C1FIFOUA23),
+            24 => Ok(SFRAddress::C1FIFOUA24),
+            25 => Ok(SFRAddress::C1FIFOUA25),
+            26 => Ok(SFRAddress::C1FIFOUA26),
+            27 => Ok(SFRAddress::C1FIFOUA27),
+            28 => Ok(SFRAddress::C1FIFOUA28),
+            29 => Ok(SFRAddress::C1FIFOUA29),
+            30 => Ok(SFRAddress::C1FIFOUA30),
+            31 => Ok(SFRAddress::C1FIFOUA31),
+            _ => Err(fifo_number),
+        }
+    }
+}
+
+pub mod timing {
+    use crate::generic::{Register, SFRAddress};
+    use core::convert::TryFrom;
+    use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+    pub enum Error {
+        /// No prescaler and segment combination within the chip's limits gets close to the
+        /// requested bitrate.
+        Unreachable,
+        /// The sample point has to be 1 to 1000 tenths of a percent.
+        InvalidSamplePoint,
+    }
+
+    /// Allowed values for each bit timing field, in time quanta.
+    struct Limits {
+        brp_max: u32,
+        tseg1_min: u32,
+        tseg1_max: u32,
+        tseg2_min: u32,
+        tseg2_max: u32,
+        sjw_max: u32,
+    }
+
+    const NOMINAL_LIMITS: Limits = Limits {
+        brp_max: 256,
+        tseg1_min: 2,
+        tseg1_max: 256,
+        tseg2_min: 1,
+        tseg2_max: 128,
+        sjw_max: 128,
+    };
+
+    const DATA_LIMITS: Limits = Limits {
+        brp_max: 256,
+        tseg1_min: 1,
+        tseg1_max: 32,
+        tseg2_min: 1,
+        tseg2_max: 16,
+        sjw_max: 16,
+    };
+
+    /// A set of bit timing values. All fields hold the real values, not the minus one
+    /// encoding used in the registers.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct BitTiming {
+        /// Baud rate prescaler, SYSCLK is divided by this to get the time quantum.
+        pub brp: u16,
+        /// Time quanta from the end of the sync segment to the sample point.
+        pub tseg1: u16,
+        /// Time quanta from the sample point to the end of the bit.
+        pub tseg2: u8,
+        /// Synchronization jump width in time quanta.
+        pub sjw: u8,
+        /// Bitrate actually achieved, in bits per second.
+        pub bitrate: u32,
+        /// Difference between the requested and achieved bitrate in parts per million.
+        pub error_ppm: u32,
+        /// Difference between the requested and achieved sample point in tenths of a
+        /// percent.
+        pub sample_point_error: u16,
+    }
+
+    impl BitTiming {
+        /// Calculates timing for the nominal (arbitration) phase. `sample_point` is in tenths
+        /// of a percent, e.g. 800 for 80%.
+        pub fn nominal(sysclk: u32, bitrate: u32, sample_point: u16) -> Result<Self, Error> {
+            BitTiming::calculate(&NOMINAL_LIMITS, sysclk, bitrate, sample_point)
+        }
+
+        /// Calculates timing for the CAN FD data phase. `sample_point` is in tenths of a
+        /// percent, e.g. 800 for 80%.
+        pub fn data(sysclk: u32, bitrate: u32, sample_point: u16) -> Result<Self, Error> {
+            BitTiming::calculate(&DATA_LIMITS, sysclk, bitrate, sample_point)
+        }
+
+        /// Number of time quanta in one bit.
+        pub fn time_quanta(&self) -> u32 {
+            1 + self.tseg1 as u32 + self.tseg2 as u32
+        }
+
+        /// Achieved sample point in tenths of a percent.
+        pub fn sample_point(&self) -> u16 {
+            ((1 + self.tseg1 as u32) * 1000 / self.time_quanta()) as u16
+        }
+
+        fn calculate(
+            limits: &Limits,
+            sysclk: u32,
+            bitrate: u32,
+            sample_point: u16,
+        ) -> Result<Self, Error> {
+            if bitrate == 0 {
+                return Err(Error::Unreachable);
+            }
+            if !(1..=1000).contains(&sample_point) {
+                return Err(Error::InvalidSamplePoint);
+            }
+
+            let min_tq = 1 + limits.tseg1_min + limits.tseg2_min;
+            let max_tq = 1 + limits.tseg1_max + limits.tseg2_max;
+
+            // Several prescalers can hit the bitrate, but the segment limits may keep some of
+            // them from reaching the sample point. Pick the smallest bitrate error, then the
+            // smallest sample point error. Walking the prescaler upwards means ties go to the
+            // most time quanta per bit.
+            let mut best: Option<BitTiming> = None;
+            for brp in 1..=limits.brp_max {
+                let divisor = brp as u64 * bitrate as u64;
+                let tq = ((sysclk as u64 + divisor / 2) / divisor) as u32;
+                if tq < min_tq {
+                    break;
+                }
+                if tq > max_tq {
+                    continue;
+                }
+
+                let actual = sysclk / (brp * tq);
+                let error = ((actual as i64 - bitrate as i64).unsigned_abs() * 1_000_000
+                    / bitrate as u64) as u32;
+                let (tseg1, tseg2) = segments(limits, tq, sample_point);
+                let mut timing = BitTiming {
+                    brp: brp as u16,
+                    tseg1: tseg1 as u16,
+                    tseg2: tseg2 as u8,
+                    sjw: core::cmp::min(tseg2, limits.sjw_max) as u8,
+                    bitrate: actual,
+                    error_ppm: error,
+                    sample_point_error: 0,
+                };
+                timing.sample_point_error =
+                    (timing.sample_point() as i32 - sample_point as i32).unsigned_abs() as u16;
+
+                match best {
+                    Some(best)
+                        if (best.error_ppm, best.sample_point_error)
+                            <= (timing.error_ppm, timing.sample_point_error) => {}
+                    _ => best = Some(timing),
+                }
+            }
+
+            best.ok_or(Error::Unreachable)
+        }
+    }
+
+    /// Splits `tq` time quanta into TSEG1 and TSEG2, placing the sample point as close as
+    /// the segment limits allow.
+    fn segments(limits: &Limits, tq: u32, sample_point: u16) -> (u32, u32) {
+        let sample_tq = (tq * sample_point as u32 + 500) / 1000;
+        let mut tseg1 = sample_tq
+            .saturating_sub(1)
+            .clamp(limits.tseg1_min, limits.tseg1_max);
+        let mut tseg2 = tq - 1 - tseg1;
+        if tseg2 < limits.tseg2_min {
+            tseg2 = limits.tseg2_min;
+            tseg1 = tq - 1 - tseg2;
+        } else if tseg2 > limits.tseg2_max {
+            tseg2 = limits.tseg2_max;
+            tseg1 = tq - 1 - tseg2;
+        }
+        (tseg1, tseg2)
+    }
+
+    bitfield! {
+        pub struct C1NBTCFG(u32);
+        impl Debug;
+        u8;
+        _sjw, _set_sjw: 6, 0;
+        _tseg2, _set_tseg2: 14, 8;
+        _tseg1, _set_tseg1: 23, 16;
+        _brp, _set_brp: 31, 24;
+    }
+
+    impl C1NBTCFG {
+        pub fn timing(&self, sysclk: u32) -> BitTiming {
+            timing_from_fields(
+                sysclk,
+                self._brp(),
+                self._tseg1(),
+                self._tseg2(),
+                self._sjw(),
+            )
+        }
+
+        /// Fields of 0 are written as 1, the smallest value the register can hold.
+        pub fn set_timing(&mut self, timing: &BitTiming) {
+            self._set_brp(timing.brp.saturating_sub(1) as u8);
+            self._set_tseg1(timing.tseg1.saturating_sub(1) as u8);
+            self._set_tseg2(timing.tseg2.saturating_sub(1));
+            self._set_sjw(timing.sjw.saturating_sub(1));
+        }
+    }
+
+    impl Register for C1NBTCFG {
+        fn address() -> SFRAddress {
+            SFRAddress::C1NBTCFG
+        }
+    }
+
+    impl From<C1NBTCFG> for u32 {
+        fn from(reg: C1NBTCFG) -> Self {
+            reg.0
+        }
+    }
+
+    bitfield! {
+        pub struct C1DBTCFG(u32);
+        impl Debug;
+        u8;
+        _sjw, _set_sjw: 3, 0;
+        _tseg2, _set_tseg2: 11, 8;
+        _tseg1, _set_tseg1: 20, 16;
+        _brp, _set_brp: 31, 24;
+    }
+
+    impl C1DBTCFG {
+        pub fn timing(&self, sysclk: u32) -> BitTiming {
+            timing_from_fields(
+                sysclk,
+                self._brp(),
+                self._tseg1(),
+                self._tseg2(),
+                self._sjw(),
+            )
+        }
+
+        /// Fields of 0 are written as 1, the smallest value the register can hold.
+        pub fn set_timing(&mut self, timing: &BitTiming) {
+            self._set_brp(timing.brp.saturating_sub(1) as u8);
+            self._set_tseg1(timing.tseg1.saturating_sub(1) as u8);
+            self._set_tseg2(timing.tseg2.saturating_sub(1));
+            self._set_sjw(timing.sjw.saturating_sub(1));
+        }
+    }
+
+    impl Register for C1DBTCFG {
+        fn address() -> SFRAddress {
+            SFRAddress::C1DBTCFG
+        }
+    }
+
+    impl From<C1DBTCFG> for u32 {
+        fn from(reg: C1DBTCFG) -> Self {
+            reg.0
+        }
+    }
+
+    /// Rebuilds a BitTiming from raw register fields. The errors are left at 0 since the
+    /// requested bitrate and sample point aren't known.
+    fn timing_from_fields(sysclk: u32, brp: u8, tseg1: u8, tseg2: u8, sjw: u8) -> BitTiming {
+        let mut timing = BitTiming {
+            brp: brp as u16 + 1,
+            tseg1: tseg1 as u16 + 1,
+            tseg2: tseg2 + 1,
+            sjw: sjw + 1,
+            bitrate: 0,
+            error_ppm: 0,
+            sample_point_error: 0,
+        };
+        timing.bitrate = sysclk / (timing.brp as u32 * timing.time_quanta());
+        timing
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
+    #[repr(u8)]
+    pub enum TdcMode {
+        Disabled = 0,
+        /// TDCV is taken from the register instead of being measured
+        Manual = 1,
+        Auto = 2,
+    }
+
+    bitfield! {
+        pub struct C1TDC(u32);
+        impl Debug;
+        u8;
+        pub tdcv, set_tdcv: 5, 0;
+        i8, _tdco, _set_tdco: 14, 8;
+        _tdcmod, _set_tdcmod: 17, 16;
+        pub sid11en, set_sid11en: 24;
+        pub edgflten, set_edgflten: 25;
+    }
+
+    impl C1TDC {
+        pub fn tdcmode(&self) -> TdcMode {
+            match TdcMode::try_from(self._tdcmod()) {
+                Ok(val) => val,
+                // 0b11 is also auto mode
+                Err(_) => TdcMode::Auto,
+            }
+        }
+
+        pub fn set_tdcmode(&mut self, mode: TdcMode) {
+            self._set_tdcmod(mode.into());
+        }
+
+        /// Offset in SYSCLK cycles, -64 to 63.
+        pub fn tdco(&self) -> i8 {
+            self._tdco()
+        }
+
+        /// Offset in SYSCLK cycles, values outside of -64 to 63 are clamped.
+        pub fn set_tdco(&mut self, offset: i8) {
+            self._set_tdco(offset.clamp(-64, 63));
+        }
+    }
+
+    impl Register for C1TDC {
+        fn address() -> SFRAddress {
+            SFRAddress::C1TDC
+        }
+    }
+
+    impl From<C1TDC> for u32 {
+        fn from(reg: C1TDC) -> Self {
+            reg.0
+        }
+    }
+
+    /// Transmitter delay compensation offset that puts the secondary sample point at the data
+    /// phase sample point, in SYSCLK cycles.
+    pub fn transmitter_delay_offset(data_timing: &BitTiming) -> i8 {
+        core::cmp::min(data_timing.brp as u32 * data_timing.tseg1 as u32, 63) as i8
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn nominal_500k_from_40mhz() {
+            let timing = match BitTiming::nominal(40_000_000, 500_000, 800) {
+                Ok(timing) => timing,
+                Err(_) => panic!("500k should be reachable"),
+            };
+            assert_eq!(timing.brp, 1);
+            assert_eq!(timing.time_quanta(), 80);
+            assert_eq!(timing.sample_point(), 800);
+            assert_eq!(timing.error_ppm, 0);
+            assert_eq!(timing.sample_point_error, 0);
+        }
+
+        #[test]
+        fn nominal_prefers_prescaler_reaching_sample_point() {
+            // BRP 1 would need a TSEG1 of 279, past the limit of 256.
+            let timing = match BitTiming::nominal(40_000_000, 125_000, 875) {
+                Ok(timing) => timing,
+                Err(_) => panic!("125k should be reachable"),
+            };
+            assert_eq!(timing.brp, 2);
+            assert_eq!(timing.sample_point(), 875);
+            assert_eq!(timing.error_ppm, 0);
+            assert_eq!(timing.sample_point_error, 0);
+        }
+
+        #[test]
+        fn data_prefers_prescaler_reaching_sample_point() {
+            // BRP 8 gives 40 time quanta, but TSEG1 can't go past 32.
+            let timing = match BitTiming::data(40_000_000, 125_000, 875) {
+                Ok(timing) => timing,
+                Err(_) => panic!("125k should be reachable"),
+            };
+            assert_eq!(timing.brp, 10);
+            assert_eq!(timing.sample_point(), 875);
+            assert_eq!(timing.error_ppm, 0);
+            assert_eq!(timing.sample_point_error, 0);
+        }
+
+        #[test]
+        fn unreachable_sample_point_is_reported() {
+            let timing = match BitTiming::data(40_000_000, 8_000_000, 500) {
+                Ok(timing) => timing,
+                Err(_) => panic!("8M should be reachable"),
+            };
+            assert_eq!(timing.time_quanta(), 5);
+            assert_eq!(timing.sample_point(), 600);
+            assert_eq!(timing.sample_point_error, 100);
+        }
+
+        #[test]
+        fn sample_point_out_of_range_is_rejected() {
+            for &sample_point in &[0, 1001, 1200] {
+                match BitTiming::nominal(40_000_000, 500_000, sample_point) {
+                    Err(Error::InvalidSamplePoint) => (),
+                    _ => panic!("expected an invalid sample point"),
+                }
+            }
+        }
+
+        #[test]
+        fn sample_point_at_end_of_bit_keeps_phase_two() {
+            let timing = match BitTiming::data(40_000_000, 2_000_000, 1000) {
+                Ok(timing) => timing,
+                Err(_) => panic!("2M should be reachable"),
+            };
+            assert_eq!(timing.tseg2, 1);
+        }
+
+        #[test]
+        fn zeroed_timing_writes_smallest_values() {
+            let timing = BitTiming {
+                brp: 0,
+                tseg1: 0,
+                tseg2: 0,
+                sjw: 0,
+                bitrate: 0,
+                error_ppm: 0,
+                sample_point_error: 0,
+            };
+            let mut c1nbtcfg = C1NBTCFG(0xFFFF_FFFF);
+            c1nbtcfg.set_timing(&timing);
+            let written = c1nbtcfg.timing(40_000_000);
+            assert_eq!(written.brp, 1);
+            assert_eq!(written.tseg1, 1);
+            assert_eq!(written.tseg2, 1);
+            assert_eq!(written.sjw, 1);
+        }
+    }
+}
+
+pub mod timestamp {
+    use crate::generic::{Register, SFRAddress};
+
+    bitfield! {
+        pub struct C1TSCON(u32);
+        impl Debug;
+        u16;
+        _tbcpre, _set_tbcpre: 9, 0;
+        pub tbcen, set_tbcen: 16;
+        pub tseof, set_tseof: 17;
+        pub tsres, set_tsres: 18;
+    }
+
+    impl C1TSCON {
+        /// SYSCLK is divided by this to clock the time base counter, 1 to 1024.
+        pub fn prescaler(&self) -> u16 {
+            self._tbcpre() + 1
+        }
+
+        /// 1 to 1024, anything else is clamped.
+        pub fn set_prescaler(&mut self, prescaler: u16) {
+            self._set_tbcpre(prescaler.clamp(1, 1024) - 1);
+        }
+    }
+
+    impl Register for C1TSCON {
+        fn address() -> SFRAddress {
+            SFRAddress::C1TSCON
+        }
+    }
+
+    impl From<C1TSCON> for u32 {
+        fn from(reg: C1TSCON) -> Self {
+            reg.0
+        }
+    }
+
+    bitfield! {
+        pub struct C1TBC(u32);
+        impl Debug;
+        u32;
+        pub tbc, set_tbc: 31, 0;
+    }
+
+    impl Register for C1TBC {
+        fn address() -> SFRAddress {
+            SFRAddress::C1TBC
+        }
+    }
+
+    impl From<C1TBC> for u32 {
+        fn from(reg: C1TBC) -> Self {
+            reg.0
+        }
+    }
+
+    /// Converts time base counter ticks to microseconds.
+    pub fn ticks_to_micros(ticks: u64, prescaler: u16, sysclk: u32) -> u64 {
+        (ticks as u128 * prescaler as u128 * 1_000_000 / sysclk as u128) as u64
+    }
+
+    /// Extends 32 bit timestamps into a 64 bit count that keeps increasing across rollovers.
+    /// It has to see at least one timestamp per rollover period, otherwise whole periods
+    /// are lost.
+    #[derive(Default)]
+    pub struct Extender {
+        last: u32,
+        rollovers: u32,
+    }
+
+    impl Extender {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Timestamps must be passed in the order they were captured.
+        pub fn extend(&mut self, ticks: u32) -> u64 {
+            if ticks < self.last {
+                self.rollovers += 1;
+            }
+            self.last = ticks;
+            ((self.rollovers as u64) << 32) | ticks as u64
+        }
+    }
+}
+
+pub mod interrupt {
+    use crate::generic::{Register, SFRAddress};
+
+    bitfield! {
+        pub struct C1INT(u32);
+        impl Debug;
+        u8;
+        // Flags
+        pub txif, _: 0;
+        pub rxif, _: 1;
+        pub tbcif, set_tbcif: 2;
+        pub modif, set_modif: 3;
+        pub tefif, _: 4;
+        pub eccif, _: 8;
+        pub spicrcif, _: 9;
+        pub txatif, _: 10;
+        pub rxovif, _: 11;
+        pub serrif, set_serrif: 12;
+        pub cerrif, set_cerrif: 13;
+        pub wakif, set_wakif: 14;
+        pub ivmif, set_ivmif: 15;
+        // Enables
+        pub txie, set_txie: 16;
+        pub rxie, set_rxie: 17;
+        pub tbcie, set_tbcie: 18;
+        pub modie, set_modie: 19;
+        pub tefie, set_tefie: 20;
+        pub eccie, set_eccie: 24;
+        pub spicrcie, set_spicrcie: 25;
+        pub txatie, set_txatie: 26;
+        pub rxovie, set_rxovie: 27;
+        pub serrie, set_serrie: 28;
+        pub cerrie, set_cerrie: 29;
+        pub wakie, set_wakie: 30;
+        pub ivmie, set_ivmie: 31;
+    }
+
+    impl C1INT {
+        /// Mask of the flags that are cleared by writing 0. The others mirror FIFO or
+        /// module status and have to be cleared at their source.
+        pub fn clearable_flags() -> u32 {
+            0b1111_0000_0000_1100
+        }
+
+        /// Value to write back to clear the flags in `mask`, keeping the enables as read.
+        /// Writing 1 to a flag leaves it alone, so flags raised since the read survive.
+        pub fn clear_value(&self, mask: u32) -> u32 {
+            (self.0 | 0xFFFF) & !mask
+        }
+    }
+
+    impl Register for C1INT {
+        fn address() -> SFRAddress {
+            SFRAddress::C1INT
+        }
+    }
+
+    impl From<C1INT> for u32 {
+        fn from(reg: C1INT) -> Self {
+            reg.0
+        }
+    }
+
+    /// Interrupt source reported by C1VEC.ICODE.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum InterruptCode {
+        /// FIFO interrupt, 0 being the TXQ
+        FIFO(u8),
+        NoInterrupt,
+        BusError,
+        WakeUp,
+        ReceiveOverflow,
+        AddressError,
+        MessageBufferOverflow,
+        TimeBaseCounterOverflow,
+        ModeChange,
+        InvalidMessage,
+        TransmitEventFIFO,
+        TransmitAttempt,
+        Reserved(u8),
+    }
+
+    impl From<u8> for InterruptCode {
+        fn from(code: u8) -> Self {
+            match code {
+                0..=0x1F => InterruptCode::FIFO(code),
+                0x40 => InterruptCode::NoInterrupt,
+                0x41 => InterruptCode::BusError,
+                0x42 => InterruptCode::WakeUp,
+                0x43 => InterruptCode::ReceiveOverflow,
+                0x44 => InterruptCode::AddressError,
+                0x45 => InterruptCode::MessageBufferOverflow,
+                0x46 => InterruptCode::TimeBaseCounterOverflow,
+                0x47 => InterruptCode::ModeChange,
+                0x48 => InterruptCode::InvalidMessage,
+                0x49 => InterruptCode::TransmitEventFIFO,
+                0x4A => InterruptCode::TransmitAttempt,
+                _ => InterruptCode::Reserved(code),
+            }
+        }
+    }
+
+    /// Value of TXCODE and RXCODE when no FIFO has an interrupt pending.
+    const NO_FIFO_INTERRUPT: u8 = 0x40;
+
+    bitfield! {
+        pub struct C1VEC(u32);
+        impl Debug;
+        u8;
+        _icode, _: 6, 0;
+        pub filhit, _: 12, 8;
+        _txcode, _: 22, 16;
+        _rxcode, _: 30, 24;
+    }
+
+    impl C1VEC {
+        /// Highest priority interrupt currently pending.
+        pub fn icode(&self) -> InterruptCode {
+            InterruptCode::from(self._icode())
+        }
+
+        /// Highest priority transmit FIFO with an interrupt pending, 0 being the TXQ.
+        pub fn txcode(&self) -> Option<u8> {
+            match self._txcode() {
+                NO_FIFO_INTERRUPT => None,
+                code => Some(code),
+            }
+        }
+
+        /// Highest priority receive FIFO with an interrupt pending.
+        pub fn rxcode(&self) -> Option<u8> {
+            match self._rxcode() {
+                NO_FIFO_INTERRUPT => None,
+                code => Some(code),
+            }
+        }
+    }
+
+    impl Register for C1VEC {
+        fn address() -> SFRAddress {
+            SFRAddress::C1VEC
+        }
+    }
+
+    impl From<C1VEC> for u32 {
+        fn from(reg: C1VEC) -> Self {
+            reg.0
+        }
+    }
+
+    /// Something the controller flagged, as reported by Controller::service_interrupts.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum Event {
+        /// Receive FIFO n has a message waiting
+        Received(u8),
+        /// Transmit FIFO n has room for another message, 0 being the TXQ
+        TransmitSpace(u8),
+        /// Receive FIFO n dropped a message because it was full
+        ReceiveOverflow(u8),
+        /// Transmit FIFO n gave up on a message after running out of attempts, 0 being the TXQ
+        TransmitAttemptsExhausted(u8),
+        TransmitEventFIFO,
+        TimeBaseCounterOverflow,
+        ModeChange,
+        ECCError,
+        SPICRCError,
+        SystemError,
+        BusError,
+        WakeUp,
+        InvalidMessage,
+    }
+
+    /// Iterator over the events found in one pass over the interrupt registers. FIFO events
+    /// are yielded lowest FIFO number first.
+    pub struct Events {
+        flags: u32,
+        received: u32,
+        transmit_space: u32,
+        receive_overflow: u32,
+        transmit_attempts: u32,
+    }
+
+    impl Events {
+        /// Builds the event list from C1INT and the raw values of C1RXIF, C1TXIF, C1RXOVIF
+        /// and C1TXATIF.
+        pub fn new(c1int: C1INT, c1rxif: u32, c1txif: u32, c1rxovif: u32, c1txatif: u32) -> Self {
+            // FIFO level flags are handled through the per-FIFO registers instead.
+            let fifo_flags = (1 << 0) | (1 << 1) | (1 << 10) | (1 << 11);
+            Events {
+                flags: c1int.0 & 0b1111_0011_0001_1111 & !fifo_flags,
+                received: c1rxif,
+                transmit_space: c1txif,
+                receive_overflow: c1rxovif,
+                transmit_attempts: c1txatif,
+            }
+        }
+    }
+
+    /// Clears and returns the index of the lowest set bit.
+    fn pop_lowest_bit(bits: &mut u32) -> Option<u8> {
+        if *bits == 0 {
+            return None;
+        }
+        let index = bits.trailing_zeros();
+        *bits &= !(1 << index);
+        Some(index as u8)
+    }
+
+    impl Iterator for Events {
+        type Item = Event;
+
+        fn next(&mut self) -> Option<Event> {
+            if let Some(fifo) = pop_lowest_bit(&mut self.receive_overflow) {
+                return Some(Event::ReceiveOverflow(fifo));
+            }
+            if let Some(fifo) = pop_lowest_bit(&mut self.received) {
+                return Some(Event::Received(fifo));
+            }
+            if let Some(fifo) = pop_lowest_bit(&mut self.transmit_attempts) {
+                return Some(Event::TransmitAttemptsExhausted(fifo));
+            }
+            if let Some(fifo) = pop_lowest_bit(&mut self.transmit_space) {
+                return Some(Event::TransmitSpace(fifo));
+            }
+
+            let bit = pop_lowest_bit(&mut self.flags)?;
+            Some(match bit {
+                2 => Event::TimeBaseCounterOverflow,
+                3 => Event::ModeChange,
+                4 => Event::TransmitEventFIFO,
+                8 => Event::ECCError,
+                9 => Event::SPICRCError,
+                12 => Event::SystemError,
+                13 => Event::BusError,
+                14 => Event::WakeUp,
+                // Only bit 15 is left once the reserved bits are masked off.
+                _ => Event::InvalidMessage,
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn interrupt_codes_follow_icode_table() {
+            assert_eq!(InterruptCode::from(0), InterruptCode::FIFO(0));
+            assert_eq!(InterruptCode::from(0x1F), InterruptCode::FIFO(31));
+            assert_eq!(InterruptCode::from(0x20), InterruptCode::Reserved(0x20));
+            assert_eq!(InterruptCode::from(0x3F), InterruptCode::Reserved(0x3F));
+            assert_eq!(InterruptCode::from(0x40), InterruptCode::NoInterrupt);
+            assert_eq!(InterruptCode::from(0x41), InterruptCode::BusError);
+            assert_eq!(InterruptCode::from(0x42), InterruptCode::WakeUp);
+            assert_eq!(InterruptCode::from(0x43), InterruptCode::ReceiveOverflow);
+            assert_eq!(InterruptCode::from(0x44), InterruptCode::AddressError);
+            assert_eq!(
+                InterruptCode::from(0x45),
+                InterruptCode::MessageBufferOverflow
+            );
+            assert_eq!(
+                InterruptCode::from(0x46),
+                InterruptCode::TimeBaseCounterOverflow
+            );
+            assert_eq!(InterruptCode::from(0x47), InterruptCode::ModeChange);
+            assert_eq!(InterruptCode::from(0x48), InterruptCode::InvalidMessage);
+            assert_eq!(InterruptCode::from(0x49), InterruptCode::TransmitEventFIFO);
+            assert_eq!(InterruptCode::from(0x4A), InterruptCode::TransmitAttempt);
+            assert_eq!(InterruptCode::from(0x4B), InterruptCode::Reserved(0x4B));
+        }
+
+        #[test]
+        fn vector_decodes_fields() {
+            let c1vec = C1VEC(0x0305_0000 | (7 << 8) | 0x03);
+            assert_eq!(c1vec.icode(), InterruptCode::FIFO(3));
+            assert_eq!(c1vec.filhit(), 7);
+            assert_eq!(c1vec.txcode(), Some(5));
+            assert_eq!(c1vec.rxcode(), Some(3));
+        }
+
+        #[test]
+        fn vector_reports_no_interrupt() {
+            // Value of C1VEC after reset.
+            let c1vec = C1VEC(0x4040_0040);
+            assert_eq!(c1vec.icode(), InterruptCode::NoInterrupt);
+            assert_eq!(c1vec.txcode(), None);
+            assert_eq!(c1vec.rxcode(), None);
+
+            // TXQ interrupts use code 0, which isn't "none".
+            assert_eq!(C1VEC(0x4000_0000).txcode(), Some(0));
+        }
+
+        #[test]
+        fn flags_map_to_events() {
+            let expected = [
+                (2, Event::TimeBaseCounterOverflow),
+                (3, Event::ModeChange),
+                (4, Event::TransmitEventFIFO),
+                (8, Event::ECCError),
+                (9, Event::SPICRCError),
+                (12, Event::SystemError),
+                (13, Event::BusError),
+                (14, Event::WakeUp),
+                (15, Event::InvalidMessage),
+            ];
+            for &(bit, event) in expected.iter() {
+                let mut events = Events::new(C1INT(1 << bit), 0, 0, 0, 0);
+                assert_eq!(events.next(), Some(event));
+                assert_eq!(events.next(), None);
+            }
+        }
+
+        #[test]
+        fn fifo_level_and_enable_bits_are_masked() {
+            // TXIF, RXIF, TXATIF and RXOVIF, reserved flags and every enable bit.
+            let c1int = C1INT(0xFFFF_0000 | (1 << 0) | (1 << 1) | (1 << 10) | (1 << 11) | 0xE0);
+            assert_eq!(Events::new(c1int, 0, 0, 0, 0).next(), None);
+        }
+
+        #[test]
+        fn fifo_events_come_first_lowest_fifo_first() {
+            let c1int = C1INT((1 << 14) | (1 << 2));
+            let events = Events::new(c1int, 0b1010, 0b0001, 0b1000_0000, 0b100);
+            let expected = [
+                Event::ReceiveOverflow(7),
+                Event::Received(1),
+                Event::Received(3),
+                Event::TransmitAttemptsExhausted(2),
+                Event::TransmitSpace(0),
+                Event::TimeBaseCounterOverflow,
+                Event::WakeUp,
+            ];
+            assert!(events.eq(expected.iter().cloned()));
+        }
+
+        #[test]
+        fn clear_value_only_clears_mask() {
+            let c1int = C1INT((1 << 30) | (1 << 14) | (1 << 2));
+            let value = c1int.clear_value(1 << 14);
+            assert_eq!(value & 0xFFFF, 0xFFFF & !(1 << 14));
+            assert_eq!(value >> 16, 1 << 14);
+        }
+    }
+}
+
+pub mod bus {
+    use crate::generic::{Register, SFRAddress};
+
+    bitfield! {
+        pub struct C1TREC(u32);
+        impl Debug;
+        u8;
+        pub rec, _: 7, 0;
+        pub tec, _: 15, 8;
+        pub ewarn, _: 16;
+        pub rxwarn, _: 17;
+        pub txwarn, _: 18;
+        pub rxbp, _: 19;
+        pub txbp, _: 20;
+        pub txbo, _: 21;
+    }
+
+    impl C1TREC {
+        pub fn bus_state(&self) -> BusState {
+            if self.txbo() {
+                BusState::BusOff
+            } else if self.txbp() || self.rxbp() {
+                BusState::ErrorPassive
+            } else if self.ewarn() {
+                BusState::ErrorWarning
+            } else {
+                BusState::ErrorActive
+            }
+        }
+    }
+
+    impl Register for C1TREC {
+        fn address() -> SFRAddress {
+            SFRAddress::C1TREC
+        }
+    }
+
+    impl From<C1TREC> for u32 {
+        fn from(reg: C1TREC) -> Self {
+            reg.0
+        }
+    }
+
+    /// Fault confinement state of the node.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum BusState {
+        /// Both error counters are below 96
+        ErrorActive,
+        /// An error counter reached 96
+        ErrorWarning,
+        /// An error counter reached 128, the node only sends passive error flags
+        ErrorPassive,
+        /// The transmit error counter passed 255, the node no longer takes part in bus traffic
+        BusOff,
+    }
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct BusStatus {
+        pub state: BusState,
+        pub transmit_error_count: u8,
+        pub receive_error_count: u8,
+    }
+
+    impl From<C1TREC> for BusStatus {
+        fn from(reg: C1TREC) -> Self {
+            BusStatus {
+                state: reg.bus_state(),
+                transmit_error_count: reg.tec(),
+                receive_error_count: reg.rec(),
+            }
+        }
+    }
+
+    bitfield! {
+        pub struct C1BDIAG0(u32);
+        impl Debug;
+        u8;
+        /// Nominal bitrate receive error counter
+        pub nrerrcnt, set_nrerrcnt: 7, 0;
+        /// Nominal bitrate transmit error counter
+        pub nterrcnt, set_nterrcnt: 15, 8;
+        /// Data bitrate receive error counter
+        pub drerrcnt, set_drerrcnt: 23, 16;
+        /// Data bitrate transmit error counter
+        pub dterrcnt, set_dterrcnt: 31, 24;
+    }
+
+    impl Register for C1BDIAG0 {
+        fn address() -> SFRAddress {
+            SFRAddress::C1BDIAG0
+        }
+    }
+
+    impl From<C1BDIAG0> for u32 {
+        fn from(reg: C1BDIAG0) -> Self {
+            reg.0
+        }
+    }
+
+    bitfield! {
+        pub struct C1BDIAG1(u32);
+        impl Debug;
+        u8;
+        /// Error free message counter
+        pub u16, efmsgcnt, set_efmsgcnt: 15, 0;
+        pub nbit0err, set_nbit0err: 16;
+        pub nbit1err, set_nbit1err: 17;
+        pub nackerr, set_nackerr: 18;
+        pub nformerr, set_nformerr: 19;
+        pub nstuferr, set_nstuferr: 20;
+        pub ncrcerr, set_ncrcerr: 21;
+        pub txboerr, set_txboerr: 23;
+        pub dbit0err, set_dbit0err: 24;
+        pub dbit1err, set_dbit1err: 25;
+        pub dformerr, set_dformerr: 27;
+        pub dstuferr, set_dstuferr: 28;
+        pub dcrcerr, set_dcrcerr: 29;
+        /// ESI flag of a received CAN FD message was set
+        pub esi, set_esi: 30;
+        /// DLC mismatch during transmission
+        pub dlcmm, set_dlcmm: 31;
+    }
+
+    impl Register for C1BDIAG1 {
+        fn address() -> SFRAddress {
+            SFRAddress::C1BDIAG1
+        }
+    }
+
+    impl From<C1BDIAG1> for u32 {
+        fn from(reg: C1BDIAG1) -> Self {
+            reg.0
+        }
+    }
+
+    /// Snapshot of the bus diagnostic registers. Error flags stay set until cleared with
+    /// Controller::clear_diagnostics.
+    pub struct Diagnostics {
+        pub counters: C1BDIAG0,
+        pub flags: C1BDIAG1,
+    }
+}
+
+pub mod filter {
+    use crate::generic::SFRAddress;
+    use crate::message::{ExtendedId, Id, StandardId};
+
+    /// Number of acceptance filters, each with its own mask.
+    pub const FILTER_COUNT: u8 = 32;
+
+    /// C1FLTCONn holds a byte of control bits for each of four filters, so the accessors
+    /// take the filter number.
+    #[derive(Debug)]
+    pub struct ControlRegister(pub u32);
+
+    impl ControlRegister {
+        /// Whether filter_number (0 to 31) is enabled.
+        pub fn enabled(&self, filter_number: u8) -> bool {
+            self.0 & (1 << (control_shift(filter_number) + 7)) != 0
+        }
+
+        pub fn set_enabled(&mut self, filter_number: u8, enabled: bool) {
+            let bit = 1 << (control_shift(filter_number) + 7);
+            if enabled {
+                self.0 |= bit;
+            } else {
+                self.0 &= !bit;
+            }
+        }
+
+        /// FIFO that messages accepted by filter_number (0 to 31) are stored in.
+        pub fn fifo(&self, filter_number: u8) -> u8 {
+            ((self.0 >> control_shift(filter_number)) & 0b1_1111) as u8
+        }
+
+        pub fn set_fifo(&mut self, filter_number: u8, fifo_number: u8) {
+            let shift = control_shift(filter_number);
+            self.0 = (self.0 & !(0b1_1111 << shift)) | ((fifo_number as u32 & 0b1_1111) << shift);
+        }
+    }
+
+    /// Each filter gets a byte of its C1FLTCON register.
+    fn control_shift(filter_number: u8) -> u32 {
+        (filter_number as u32 % 4) * 8
+    }
+
+    impl From<ControlRegister> for u32 {
+        fn from(reg: ControlRegister) -> Self {
+            reg.0
+        }
+    }
+
+    bitfield! {
+        pub struct ObjectRegister(u32);
+        impl Debug;
+        u8;
+        pub u16, sid, set_sid: 10, 0;
+        pub u32, eid, set_eid: 28, 11;
+        pub sid11, set_sid11: 29;
+        pub exide, set_exide: 30;
+    }
+
+    impl ObjectRegister {
+        pub fn identifier(&self) -> Id {
+            Id::from_fields(self.sid(), self.eid(), self.exide())
+        }
+
+        pub fn set_identifier(&mut self, identifier: Id) {
+            let (sid, eid, ide) = identifier.to_fields();
+            self.set_sid(sid);
+            self.set_eid(eid);
+            self.set_exide(ide);
+        }
+    }
+
+    impl From<ObjectRegister> for u32 {
+        fn from(reg: ObjectRegister) -> Self {
+            reg.0
+        }
+    }
+
+    bitfield! {
+        pub struct MaskRegister(u32);
+        impl Debug;
+        u8;
+        pub u16, msid, set_msid: 10, 0;
+        pub u32, meid, set_meid: 28, 11;
+        pub msid11, set_msid11: 29;
+        pub mide, set_mide: 30;
+    }
+
+    impl From<MaskRegister> for u32 {
+        fn from(reg: MaskRegister) -> Self {
+            reg.0
+        }
+    }
+
+    /// An acceptance filter and its mask.
+    #[derive(Copy, Clone)]
+    pub struct Filter {
+        pub id: Id,
+        /// Identifier bits that have to match, laid out like the identifier: 11 bits for a
+        /// standard id, 29 for an extended one. Standard filters only match standard frames
+        /// and extended filters only match extended frames.
+        pub mask: u32,
+        /// FIFO accepted messages are stored in, 1 to 31
+        pub fifo: u8,
+        /// Leading data bits to match, only used by standard filters
+        pub data: DataBits,
+    }
+
+    /// Maximum number of data bits C1CON.DNCNT can compare.
+    pub const MAX_DATA_BITS: u8 = 18;
+
+    /// Leading data bits of a standard frame for DeviceNet filtering. When C1CON.DNCNT is
+    /// non-zero the controller compares that many bits of the payload, starting with the
+    /// most significant bit of the first byte, against the EID bits of standard filters.
+    #[derive(Copy, Clone, Debug)]
+    pub struct DataBits {
+        /// Bit 17 is the first data bit, bit 0 the eighteenth
+        pub value: u32,
+        /// Which of the bits in value have to match
+        pub mask: u32,
+    }
+
+    impl DataBits {
+        /// Matches any payload.
+        pub fn any() -> Self {
+            DataBits { value: 0, mask: 0 }
+        }
+
+        /// Matches the first `count` bits of `data`, up to MAX_DATA_BITS. Bits beyond the
+        /// end of `data` are left out. Only as many bits as C1CON.DNCNT are compared.
+        pub fn leading(data: &[u8], count: u8) -> Self {
+            let count = core::cmp::min(
+                core::cmp::min(count, MAX_DATA_BITS) as usize,
+                data.len() * 8,
+            );
+            let mut bits: u32 = 0;
+            for (index, byte) in data.iter().take(3).enumerate() {
+                bits |= (*byte as u32) << (16 - 8 * index);
+            }
+            let mask = !(0xFF_FFFFu32 >> count) & 0xFF_FFFF;
+            DataBits {
+                value: (bits & mask) >> 6,
+                mask: mask >> 6,
+            }
+        }
+    }
+
+    impl Filter {
+        /// A filter that accepts only the given identifier.
+        pub fn exact(id: Id, fifo: u8) -> Self {
+            Filter {
+                id,
+                mask: match id {
+                    Id::Standard(_) => 0x7FF,
+                    Id::Extended(_) => 0x1FFF_FFFF,
+                },
+                fifo,
+                data: DataBits::any(),
+            }
+        }
+
+        /// Also match on the leading data bits, see DataBits.
+        pub fn with_data(mut self, data: DataBits) -> Self {
+            self.data = data;
+            self
+        }
+
+        pub fn object_register(&self) -> ObjectRegister {
+            let mut object = ObjectRegister(0);
+            object.set_identifier(self.id);
+            if let Id::Standard(_) = self.id {
+                object.set_eid(self.data.value & 0x3_FFFF);
+            }
+            object
+        }
+
+        pub fn mask_register(&self) -> MaskRegister {
+            let mut mask = MaskRegister(0);
+            mask.set_mide(true);
+            match self.id {
+                Id::Standard(_) => {
+                    mask.set_msid(self.mask as u16 & 0x7FF);
+                    mask.set_meid(self.data.mask & 0x3_FFFF);
+                }
+                Id::Extended(_) => {
+                    mask.set_msid((self.mask >> 18) as u16 & 0x7FF);
+                    mask.set_meid(self.mask & 0x3_FFFF);
+                }
+            }
+            mask
+        }
+    }
+
+    /// Identifiers a filter set should accept, and where to put them.
+    #[derive(Copy, Clone)]
+    pub struct Acceptance {
+        pub first: Id,
+        /// Inclusive, must be the same kind of identifier as first
+        pub last: Id,
+        /// FIFO accepted messages are stored in, 1 to 31
+        pub fifo: u8,
+    }
+
+    impl Acceptance {
+        pub fn exact(id: Id, fifo: u8) -> Self {
+            Acceptance {
+                first: id,
+                last: id,
+                fifo,
+            }
+        }
+
+        pub fn range(first: Id, last: Id, fifo: u8) -> Self {
+            Acceptance { first, last, fifo }
+        }
+
+        /// Raw first and last identifiers and whether they are extended.
+        fn bounds(&self) -> (u32, u32, bool) {
+            let (first, extended) = raw_id(self.first);
+            let (last, _) = raw_id(self.last);
+            (first, last, extended)
+        }
+    }
+
+    #[derive(Debug, Eq, PartialEq)]
+    pub enum CompileError {
+        /// Holds the index of an acceptance whose FIFO isn't 1 to 31.
+        InvalidFIFO(usize),
+        /// Holds the index of an acceptance that mixes standard and extended identifiers or
+        /// whose last identifier comes before its first.
+        InvalidRange(usize),
+        /// Holds the indices of two acceptances that send the same identifier to different
+        /// FIFOs.
+        Overlap(usize, usize),
+        /// No set of at most `max_filters` filters was found that accepts every wanted
+        /// identifier without also catching identifiers meant for another FIFO.
+        TooManyFilters,
+    }
+
+    /// Result of compile(), ready to be applied with Controller::apply_filters.
+    pub struct CompiledFilters {
+        filters: [Option<Filter>; FILTER_COUNT as usize],
+        leaked: u64,
+    }
+
+    impl CompiledFilters {
+        /// Filter for each filter number, None for the ones that should stay disabled.
+        pub fn filters(&self) -> &[Option<Filter>] {
+            &self.filters
+        }
+
+        /// Number of identifiers that will be accepted without having been asked for,
+        /// because filters had to be merged to fit. None of them is one that was asked for
+        /// on another FIFO.
+        pub fn leaked(&self) -> u64 {
+            self.leaked
+        }
+    }
+
+    /// A set of identifiers matched by one filter and mask pair.
+    #[derive(Copy, Clone)]
+    struct Cube {
+        value: u32,
+        mask: u32,
+        extended: bool,
+        fifo: u8,
+        /// How many identifiers in the cube were asked for.
+        wanted: u64,
+    }
+
+    impl Cube {
+        fn width(&self) -> u32 {
+            if self.extended {
+                29
+            } else {
+                11
+            }
+        }
+
+        fn size(&self) -> u64 {
+            1 << (self.width() - self.mask.count_ones())
+        }
+
+        fn same_group(&self, other: &Cube) -> bool {
+            self.extended == other.extended && self.fifo == other.fifo
+        }
+
+        fn intersects(&self, other: &Cube) -> bool {
+            (self.value ^ other.value) & self.mask & other.mask == 0
+        }
+
+        /// Smallest cube holding both.
+        fn merge(&self, other: &Cube) -> Cube {
+            let mask = self.mask & other.mask & !(self.value ^ other.value);
+            Cube {
+                value: self.value & mask,
+                mask,
+                extended: self.extended,
+                fifo: self.fifo,
+                wanted: self.wanted + other.wanted,
+            }
+        }
+
+        fn leaked(&self) -> u64 {
+            self.size() - self.wanted
+        }
+
+        /// Lowest identifier in the cube that is at least `from`.
+        fn next_match(&self, from: u32) -> Option<u32> {
+            let differing = (from ^ self.value) & self.mask;
+            if differing == 0 {
+                return Some(from);
+            }
+
+            // Only the highest differing bit matters, everything above it already matches.
+            let high = 31 - differing.leading_zeros();
+            let base = if self.value & (1 << high) != 0 {
+                // `from` is below the cube there, so setting that bit and taking the lowest
+                // match below it is enough.
+                (from >> high) << high
+            } else {
+                // `from` is above the cube there, so a free bit further up has to be
+                // carried into.
+                let width_mask = (1u32 << self.width()) - 1;
+                let carry = !self.mask & !from & width_mask & !((2u32 << high) - 1);
+                if carry == 0 {
+                    return None;
+                }
+                let bit = carry.trailing_zeros();
+                ((from >> bit) | 1) << bit
+            };
+            Some((base & !self.mask) | self.value)
+        }
+
+        /// True if the cube holds any identifier from first to last.
+        fn meets(&self, first: u32, last: u32) -> bool {
+            match self.next_match(first) {
+                Some(id) => id <= last,
+                None => false,
+            }
+        }
+    }
+
+    /// One slot more than there are filters so an insert can go over before being merged
+    /// back down.
+    const TABLE_SIZE: usize = FILTER_COUNT as usize + 1;
+
+    /// Working set of cubes. Cubes never overlap, so the leaked counts add up exactly, and
+    /// never hold an identifier asked for on another FIFO.
+    struct Table<'a> {
+        cubes: [Option<Cube>; TABLE_SIZE],
+        acceptances: &'a [Acceptance],
+    }
+
+    impl<'a> Table<'a> {
+        fn len(&self) -> usize {
+            self.cubes.iter().filter(|cube| cube.is_some()).count()
+        }
+
+        /// Grows the cube until it doesn't intersect any other cube of its group, taking
+        /// those cubes out of the table if `absorb` is set. Also returns how many leaked
+        /// identifiers the grown over cubes held.
+        fn close(&mut self, mut cube: Cube, skip: [usize; 2], absorb: bool) -> (Cube, u64) {
+            let mut absorbed = [false; TABLE_SIZE];
+            let mut absorbed_leaked = 0;
+            loop {
+                let mut grown = false;
+                for (index, slot) in self.cubes.iter().enumerate() {
+                    if absorbed[index] || skip.contains(&index) {
+                        continue;
+                    }
+                    if let Some(other) = slot {
+                        if other.same_group(&cube) && other.intersects(&cube) {
+                            cube = cube.merge(other);
+                            absorbed_leaked += other.leaked();
+                            absorbed[index] = true;
+                            grown = true;
+                        }
+                    }
+                }
+                if !grown {
+                    break;
+                }
+            }
+            if absorb {
+                for (index, slot) in self.cubes.iter_mut().enumerate() {
+                    if absorbed[index] {
+                        *slot = None;
+                    }
+                }
+            }
+            (cube, absorbed_leaked)
+        }
+
+        /// True if the cube would overlap a cube of another FIFO or hold an identifier
+        /// asked for on another FIFO.
+        fn conflicts(&self, cube: &Cube) -> bool {
+            let foreign_cube = self.cubes.iter().flatten().any(|other| {
+                other.extended == cube.extended && other.fifo != cube.fifo && other.intersects(cube)
+            });
+            foreign_cube
+                || self.acceptances.iter().any(|acceptance| {
+                    let (first, last, extended) = acceptance.bounds();
+                    extended == cube.extended
+                        && acceptance.fifo != cube.fifo
+                        && cube.meets(first, last)
+                })
+        }
+
+        /// Adds a cube, growing it over the cubes of its group it overlaps. There is
+        /// always a free slot since the table is brought back to the limit after each
+        /// insert.
+        fn insert(&mut self, cube: Cube) -> Result<(), CompileError> {
+            let (closed, _) = self.close(cube, [usize::MAX; 2], false);
+            if self.conflicts(&closed) {
+                return Err(CompileError::TooManyFilters);
+            }
+            let (closed, _) = self.close(cube, [usize::MAX; 2], true);
+            match self.cubes.iter_mut().find(|slot| slot.is_none()) {
+                Some(slot) => {
+                    *slot = Some(closed);
+                    Ok(())
+                }
+                None => Err(CompileError::TooManyFilters),
+            }
+        }
+
+        /// Finds the pair of cubes whose merge leaks the fewest new identifiers, leaving
+        /// out the pairs marked in `excluded`. Returns the pair, the cost and the merged
+        /// cube.
+        fn cheapest_merge(
+            &mut self,
+            excluded: &[u64; TABLE_SIZE],
+        ) -> Option<(usize, usize, u64, Cube)> {
+            let mut best: Option<(usize, usize, u64, Cube)> = None;
+            for (i, excluded_pairs) in excluded.iter().enumerate() {
+                for j in i + 1..TABLE_SIZE {
+                    if excluded_pairs & (1 << j) != 0 {
+                        continue;
+                    }
+                    let (a, b) = match (self.cubes[i], self.cubes[j]) {
+                        (Some(a), Some(b)) if a.same_group(&b) => (a, b),
+                        _ => continue,
+                    };
+                    let (merged, absorbed_leaked) = self.close(a.merge(&b), [i, j], false);
+                    let cost = merged.leaked() - a.leaked() - b.leaked() - absorbed_leaked;
+                    match best {
+                        Some((_, _, best_cost, _)) if best_cost <= cost => (),
+                        _ => best = Some((i, j, cost, merged)),
+                    }
+                }
+            }
+            best
+        }
+
+        /// Merges cubes that can be joined without leaking, then keeps merging the
+        /// cheapest pairs until there are at most `limit` cubes. Merges that would take
+        /// in another FIFO's identifiers are skipped.
+        fn reduce(&mut self, limit: usize) -> Result<(), CompileError> {
+            let mut excluded = [0u64; TABLE_SIZE];
+            while let Some((i, j, cost, merged)) = self.cheapest_merge(&excluded) {
+                if cost > 0 && self.len() <= limit {
+                    break;
+                }
+                if self.conflicts(&merged) {
+                    excluded[i] |= 1 << j;
+                    continue;
+                }
+
+                let (a, b) = match (self.cubes[i].take(), self.cubes[j].take()) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => break,
+                };
+                self.insert(a.merge(&b))?;
+                excluded = [0; TABLE_SIZE];
+            }
+
+            if self.len() > limit {
+                return Err(CompileError::TooManyFilters);
+            }
+            Ok(())
+        }
+    }
+
+    /// Splits an identifier into its raw value and whether it is extended.
+    fn raw_id(id: Id) -> (u32, bool) {
+        match id {
+            Id::Standard(id) => (id.as_raw() as u32, false),
+            Id::Extended(id) => (id.as_raw(), true),
+        }
+    }
+
+    /// Calls `f` with each run of identifiers asked for on a FIFO, lowest first, with
+    /// overlapping and adjacent acceptances joined together.
+    fn for_each_run<F>(
+        acceptances: &[Acceptance],
+        extended: bool,
+        fifo: u8,
+        mut f: F,
+    ) -> Result<(), CompileError>
+    where
+        F: FnMut(u32, u32) -> Result<(), CompileError>,
+    {
+        let group = || {
+            acceptances
+                .iter()
+                .filter(move |acceptance| acceptance.fifo == fifo)
+                .map(|acceptance| acceptance.bounds())
+                .filter(move |&(_, _, kind)| kind == extended)
+        };
+
+        let mut cursor: u64 = 0;
+        loop {
+            let start = group()
+                .filter(|&(_, last, _)| last as u64 >= cursor)
+                .map(|(first, _, _)| core::cmp::max(first as u64, cursor))
+                .min();
+            let start = match start {
+                Some(start) => start,
+                None => return Ok(()),
+            };
+
+            let mut end = start;
+            loop {
+                let mut grown = false;
+                for (first, last, _) in group() {
+                    if first as u64 <= end + 1 && last as u64 > end {
+                        end = last as u64;
+                        grown = true;
+                    }
+                }
+                if !grown {
+                    break;
+                }
+            }
+
+            f(start as u32, end as u32)?;
+            cursor = end + 1;
+        }
+    }
+
+    /// Works out filter and mask pairs that accept the given identifiers, using at most
+    /// `max_filters` of the 32 filters. Each range is split into aligned blocks which are
+    /// then merged back together wherever that doesn't let anything else through. If that
+    /// still needs too many filters, the pairs that leak the fewest extra identifiers are
+    /// merged until it fits; CompiledFilters::leaked() reports how many.
+    ///
+    /// Acceptances for the same FIFO may overlap. Filters never take in an identifier asked
+    /// for on another FIFO and never overlap each other, so their order doesn't matter.
+    /// Merging is greedy, so the result is small but not guaranteed to be the smallest
+    /// possible, and TooManyFilters can come up where a cleverer split would have fit.
+    pub fn compile(
+        acceptances: &[Acceptance],
+        max_filters: usize,
+    ) -> Result<CompiledFilters, CompileError> {
+        for (index, acceptance) in acceptances.iter().enumerate() {
+            if !(1..=31).contains(&acceptance.fifo) {
+                return Err(CompileError::InvalidFIFO(index));
+            }
+            let (first, last, extended) = acceptance.bounds();
+            let (_, last_extended) = raw_id(acceptance.last);
+            if extended != last_extended || first > last {
+                return Err(CompileError::InvalidRange(index));
+            }
+            for (other_index, other) in acceptances[..index].iter().enumerate() {
+                let (other_first, other_last, other_extended) = other.bounds();
+                if other.fifo != acceptance.fifo
+                    && other_extended == extended
+                    && other_first <= last
+                    && first <= other_last
+                {
+                    return Err(CompileError::Overlap(other_index, index));
+                }
+            }
+        }
+
+        let limit = core::cmp::min(max_filters, FILTER_COUNT as usize);
+        let mut table = Table {
+            cubes: [None; TABLE_SIZE],
+            acceptances,
+        };
+
+        for (index, acceptance) in acceptances.iter().enumerate() {
+            let (_, _, extended) = acceptance.bounds();
+            let fifo = acceptance.fifo;
+            // Each FIFO and kind of identifier is handled once, at its first acceptance.
+            let seen = acceptances[..index]
+                .iter()
+                .any(|other| other.fifo == fifo && other.bounds().2 == extended);
+            if seen {
+                continue;
+            }
+
+            let width = if extended { 29 } else { 11 };
+            let full_mask = (1u32 << width) - 1;
+            for_each_run(acceptances, extended, fifo, |first, last| {
+                // Cover the run with the largest aligned power of two blocks that fit.
+                let mut start = first as u64;
+                while start <= last as u64 {
+                    let mut bits = 0;
+                    while bits < width
+                        && start & ((1 << (bits + 1)) - 1) == 0
+                        && start + (1 << (bits + 1)) - 1 <= last as u64
+                    {
+                        bits += 1;
+                    }
+
+                    table.insert(Cube {
+                        value: start as u32,
+                        mask: full_mask & !((1u32 << bits) - 1),
+                        extended,
+                        fifo,
+                        wanted: 1 << bits,
+                    })?;
+                    table.reduce(limit)?;
+
+                    start += 1 << bits;
+                }
+                Ok(())
+            })?;
+        }
+
+        let mut compiled = CompiledFilters {
+            filters: [None; FILTER_COUNT as usize],
+            leaked: 0,
+        };
+        for (slot, cube) in compiled
+            .filters
+            .iter_mut()
+            .zip(table.cubes.iter().flatten())
+        {
+            compiled.leaked += cube.leaked();
+            *slot = Some(Filter {
+                id: if cube.extended {
+                    Id::Extended(ExtendedId::new(cube.value).unwrap())
+                } else {
+                    Id::Standard(StandardId::new(cube.value as u16).unwrap())
+                },
+                mask: cube.mask,
+                fifo: cube.fifo,
+                data: DataBits::any(),
+            });
+        }
+
+        Ok(compiled)
+    }
+
+    pub fn get_filter_control_address(filter_number: u8) -> Result<SFRAddress, u8> {
+        match filter_number {
+            0..=3 => Ok(SFRAddress::C1FLTCON0),
+            4..=7 => Ok(SFRAddress::C1FLTCON1),
+            8..=11 => Ok(SFRAddress::C1FLTCON2),
+            12..=15 => Ok(SFRAddress::C1FLTCON3),
+            16..=19 => Ok(SFRAddress::C1FLTCON4),
+            20..=23 => Ok(SFRAddress::C1FLTCON5),
+            24..=27 => Ok(SFRAddress::C1FLTCON6),
+            28..=31 => Ok(SFRAddress::C1FLTCON7),
+            _ => Err(filter_number),
+        }
+    }
+
+    pub fn get_filter_object_address(filter_number: u8) -> Result<SFRAddress, u8> {
+        match filter_number {
+            0 => Ok(SFRAddress::C1FLTOBJ0),
+            1 => Ok(SFRAddress::C1FLTOBJ1),
+            2 => Ok(SFRAddress::C1FLTOBJ2),
+            3 => Ok(SFRAddress::C1FLTOBJ3),
+            4 => Ok(SFRAddress::C1FLTOBJ4),
+            5 => Ok(SFRAddress::C1FLTOBJ5),
+            6 => Ok(SFRAddress::C1FLTOBJ6),
+            7 => Ok(SFRAddress::C1FLTOBJ7),
+            8 => Ok(SFRAddress::C1FLTOBJ8),
+            9 => Ok(SFRAddress::C1FLTOBJ9),
+            10 => Ok(SFRAddress::C1FLTOBJ10),
+            11 => Ok(SFRAddress::C1FLTOBJ11),
+            12 => Ok(SFRAddress::C1FLTOBJ12),
+            13 => Ok(SFRAddress::C1FLTOBJ13),
+            14 => Ok(SFRAddress::C1FLTOBJ14),
+            15 => Ok(SFRAddress::C1FLTOBJ15),
+            16 => Ok(SFRAddress::C1FLTOBJ16),
+            17 => Ok(SFRAddress::C1FLTOBJ17),
+            18 => Ok(SFRAddress::C1FLTOBJ18),
+            19 => Ok(SFRAddress::C1FLTOBJ19),
+            20 => Ok(SFRAddress::C1FLTOBJ20),
+            21 => Ok(SFRAddress::C1FLTOBJ21),
+            22 => Ok(SFRAddress::C1FLTOBJ22),
+            23 => Ok(SFRAddress::C1FLTOBJ23),
+            24 => Ok(SFRAddress::C1FLTOBJ24),
+            25 => Ok(SFRAddress::C1FLTOBJ25),
+            26 => Ok(SFRAddress::C1FLTOBJ26),
+            27 => Ok(SFRAddress::C1FLTOBJ27),
+            28 => Ok(SFRAddress::C1FLTOBJ28),
+            29 => Ok(SFRAddress::C1FLTOBJ29),
+            30 => Ok(SFRAddress::C1FLTOBJ30),
+            31 => Ok(SFRAddress::C1FLTOBJ31),
+            _ => Err(filter_number),
+        }
+    }
+
+    pub fn get_mask_address(filter_number: u8) -> Result<SFRAddress, u8> {
+        match filter_number {
+            0 => Ok(SFRAddress::C1MASK0),
+            1 => Ok(SFRAddress::C1MASK1),
+            2 => Ok(SFRAddress::C1MASK2),
+            3 => Ok(SFRAddress::C1MASK3),
+            4 => Ok(SFRAddress::C1MASK4),
+            5 => Ok(SFRAddress::C1MASK5),
+            6 => Ok(SFRAddress::C1MASK6),
+            7 => Ok(SFRAddress::C1MASK7),
+            8 => Ok(SFRAddress::C1MASK8),
+            9 => Ok(SFRAddress::C1MASK9),
+            10 => Ok(SFRAddress::C1MASK10),
+            11 => Ok(SFRAddress::C1MASK11),
+            12 => Ok(SFRAddress::C1MASK12),
+            13 => Ok(SFRAddress::C1MASK13),
+            14 => Ok(SFRAddress::C1MASK14),
+            15 => Ok(SFRAddress::C1MASK15),
+            16 => Ok(SFRAddress::C1MASK16),
+            17 => Ok(SFRAddress::C1MASK17),
+            18 => Ok(SFRAddress::C1MASK18),
+            19 => Ok(SFRAddress::C1MASK19),
+            20 => Ok(SFRAddress::C1MASK20),
+            21 => Ok(SFRAddress::C1MASK21),
+            22 => Ok(SFRAddress::C1MASK22),
+            23 => Ok(SFRAddress::C1MASK23),
+            24 => Ok(SFRAddress::C1MASK24),
+            25 => Ok(SFRAddress::C1MASK25),
+            26 => Ok(SFRAddress::C1MASK26),
+            27 => Ok(SFRAddress::C1MASK27),
+            28 => Ok(SFRAddress::C1MASK28),
+            29 => Ok(SFRAddress::C1MASK29),
+            30 => Ok(SFRAddress::C1MASK30),
+            31 => Ok(SFRAddress::C1MASK31),
+            _ => Err(filter_number),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn standard(id: u16) -> Id {
+            Id::Standard(StandardId::new(id).unwrap())
+        }
+
+        fn extended(id: u32) -> Id {
+            Id::Extended(ExtendedId::new(id).unwrap())
+        }
+
+        fn compiled(acceptances: &[Acceptance], max_filters: usize) -> CompiledFilters {
+            match compile(acceptances, max_filters) {
+                Ok(compiled) => compiled,
+                Err(error) => panic!("compile failed: {:?}", error),
+            }
+        }
+
+        fn filter_count(compiled: &CompiledFilters) -> usize {
+            compiled.filters().iter().flatten().count()
+        }
+
+        /// Goes through every standard identifier, checking wanted ones are accepted by
+        /// exactly one filter for the right FIFO and that leaked() counts the rest.
+        fn check_standard(acceptances: &[Acceptance], compiled: &CompiledFilters) {
+            let mut leaked = 0;
+            for id in 0..=StandardId::MAX.as_raw() {
+                let mut fifos = compiled.filters().iter().flatten().filter_map(|filter| {
+                    let (filter_id, _) = raw_id(filter.id);
+                    match filter.id {
+                        Id::Standard(_) if (id as u32 ^ filter_id) & filter.mask == 0 => {
+                            Some(filter.fifo)
+                        }
+                        _ => None,
+                    }
+                });
+                let fifo = fifos.next();
+                assert_eq!(fifos.next(), None, "{:#x} matched twice", id);
+
+                let wanted = acceptances.iter().find(|acceptance| {
+                    let (first, last, extended) = acceptance.bounds();
+                    !extended && first <= id as u32 && id as u32 <= last
+                });
+                match wanted {
+                    Some(acceptance) => assert_eq!(fifo, Some(acceptance.fifo), "{:#x}", id),
+                    None if fifo.is_some() => leaked += 1,
+                    None => (),
+                }
+            }
+            assert_eq!(compiled.leaked(), leaked);
+        }
+
+        #[test]
+        fn exact_ids_get_exact_filters() {
+            let acceptances = [
+                Acceptance::exact(standard(0x7), 2),
+                Acceptance::exact(standard(0x123), 1),
+                Acceptance::exact(extended(0x18FE_F100), 3),
+            ];
+            let compiled = compiled(&acceptances, 32);
+            assert_eq!(filter_count(&compiled), 3);
+            assert_eq!(compiled.leaked(), 0);
+            for filter in compiled.filters().iter().flatten() {
+                match filter.id {
+                    Id::Standard(_) => assert_eq!(filter.mask, 0x7FF),
+                    Id::Extended(_) => assert_eq!(filter.mask, 0x1FFF_FFFF),
+                }
+            }
+            check_standard(&acceptances, &compiled);
+        }
+
+        #[test]
+        fn aligned_range_needs_one_filter() {
+            let acceptances = [Acceptance::range(
+                extended(0x18FE_F100),
+                extended(0x18FE_F1FF),
+                3,
+            )];
+            let compiled = compiled(&acceptances, 32);
+            assert_eq!(filter_count(&compiled), 1);
+            let filter = compiled.filters()[0].unwrap();
+            assert_eq!(raw_id(filter.id), (0x18FE_F100, true));
+            assert_eq!(filter.mask, 0x1FFF_FF00);
+        }
+
+        #[test]
+        fn unaligned_range_is_split_into_blocks() {
+            let acceptances = [Acceptance::range(standard(0x101), standard(0x17E), 1)];
+            let compiled = compiled(&acceptances, 32);
+            assert_eq!(filter_count(&compiled), 12);
+            assert_eq!(compiled.leaked(), 0);
+            check_standard(&acceptances, &compiled);
+        }
+
+        #[test]
+        fn neighbouring_ids_are_merged_without_leaking() {
+            let acceptances = [
+                Acceptance::exact(standard(1), 1),
+                Acceptance::exact(standard(3), 1),
+                Acceptance::exact(standard(0), 1),
+                Acceptance::exact(standard(2), 1),
+            ];
+            let compiled = compiled(&acceptances, 32);
+            assert_eq!(filter_count(&compiled), 1);
+            assert_eq!(compiled.filters()[0].unwrap().mask, 0x7FC);
+            check_standard(&acceptances, &compiled);
+        }
+
+        #[test]
+        fn overlapping_ranges_for_one_fifo_are_joined() {
+            let acceptances = [
+                Acceptance::range(standard(0x00), standard(0x0F), 1),
+                Acceptance::range(standard(0x08), standard(0x1F), 1),
+            ];
+            let compiled = compiled(&acceptances, 32);
+            assert_eq!(filter_count(&compiled), 1);
+            assert_eq!(compiled.leaked(), 0);
+            check_standard(&acceptances, &compiled);
+        }
+
+        #[test]
+        fn merging_to_fit_counts_leaks() {
+            let acceptances = [Acceptance::range(standard(0x101), standard(0x17E), 1)];
+            for &max_filters in &[1, 2, 5] {
+                let compiled = compiled(&acceptances, max_filters);
+                assert!(filter_count(&compiled) <= max_filters);
+                assert!(compiled.leaked() > 0);
+                check_standard(&acceptances, &compiled);
+            }
+        }
+
+        #[test]
+        fn merges_stay_clear_of_other_fifos() {
+            let acceptances = [
+                Acceptance::exact(standard(0x100), 1),
+                Acceptance::exact(standard(0x103), 1),
+                Acceptance::exact(standard(0x105), 2),
+            ];
+            let compiled = compiled(&acceptances, 2);
+            assert_eq!(filter_count(&compiled), 2);
+            assert_eq!(compiled.leaked(), 2);
+            check_standard(&acceptances, &compiled);
+
+            let acceptances = [
+                Acceptance::exact(standard(0x100), 1),
+                Acceptance::exact(standard(0x103), 1),
+                Acceptance::exact(standard(0x101), 2),
+            ];
+            assert_eq!(
+                compile(&acceptances, 2).err(),
+                Some(CompileError::TooManyFilters)
+            );
+        }
+
+        #[test]
+        fn limit_is_never_exceeded() {
+            let acceptances = [
+                Acceptance::exact(standard(1), 1),
+                Acceptance::exact(standard(2), 2),
+            ];
+            assert_eq!(
+                compile(&acceptances, 1).err(),
+                Some(CompileError::TooManyFilters)
+            );
+
+            let mut acceptances = [Acceptance::exact(standard(0), 1); 62];
+            for fifo in 1..=31u8 {
+                let index = 2 * (fifo as usize - 1);
+                acceptances[index] = Acceptance::exact(standard(fifo as u16), fifo);
+                acceptances[index + 1] = Acceptance::exact(extended(fifo as u32), fifo);
+            }
+            assert_eq!(
+                compile(&acceptances, 32).err(),
+                Some(CompileError::TooManyFilters)
+            );
+        }
+
+        #[test]
+        fn overlap_across_fifos_is_rejected() {
+            let acceptances = [
+                Acceptance::range(standard(0x000), standard(0x0FF), 1),
+                Acceptance::exact(standard(0x010), 2),
+            ];
+            assert_eq!(
+                compile(&acceptances, 32).err(),
+                Some(CompileError::Overlap(0, 1))
+            );
+        }
+
+        #[test]
+        fn bad_acceptances_are_rejected() {
+            assert_eq!(
+                compile(&[Acceptance::exact(standard(1), 0)], 32).err(),
+                Some(CompileError::InvalidFIFO(0))
+            );
+            assert_eq!(
+                compile(&[Acceptance::range(standard(2), standard(1), 1)], 32).err(),
+                Some(CompileError::InvalidRange(0))
+            );
+            assert_eq!(
+                compile(&[Acceptance::range(standard(1), extended(2), 1)], 32).err(),
+                Some(CompileError::InvalidRange(0))
+            );
+        }
+
+        #[test]
+        fn next_match_finds_lowest_identifier_in_cube() {
+            // 0b0x1x, free bits 0 and 2
+            let cube = Cube {
+                value: 0b0010,
+                mask: 0x7FF & !0b0101,
+                extended: false,
+                fifo: 1,
+                wanted: 0,
+            };
+            assert_eq!(cube.next_match(0), Some(0b0010));
+            assert_eq!(cube.next_match(0b0011), Some(0b0011));
+            assert_eq!(cube.next_match(0b0100), Some(0b0110));
+            assert_eq!(cube.next_match(0b1000), None);
+            assert!(cube.meets(0b0100, 0b0110));
+            assert!(!cube.meets(0b0100, 0b0101));
+        }
+
+        #[test]
+        fn first_data_bit_lands_in_eid17() {
+            let data = DataBits::leading(&[0x80], 8);
+            assert_eq!(data.value, 1 << 17);
+            assert_eq!(data.mask, 0xFF << 10);
+
+            let filter = Filter::exact(standard(0x123), 1).with_data(data);
+            assert_eq!(filter.object_register().0, (1 << 28) | 0x123);
+            assert_eq!(filter.mask_register().meid(), 0xFF << 10);
+        }
+
+        #[test]
+        fn leading_keeps_count_bits() {
+            let data = DataBits::leading(&[0xAB, 0xCD, 0xEF], 12);
+            assert_eq!(data.value, 0xABC << 6);
+            assert_eq!(data.mask, 0xFFF << 6);
+
+            let data = DataBits::leading(&[0xAB, 0xCD, 0xEF], 18);
+            assert_eq!(data.value, 0xAB_CDEF >> 6);
+            assert_eq!(data.mask, 0x3_FFFF);
+
+            // Anything past MAX_DATA_BITS is ignored.
+            let data = DataBits::leading(&[0xAB, 0xCD, 0xEF, 0x12], 32);
+            assert_eq!(data.value, 0xAB_CDEF >> 6);
+            assert_eq!(data.mask, 0x3_FFFF);
+        }
+
+        #[test]
+        fn leading_stops_at_end_of_data() {
+            let data = DataBits::leading(&[0xFF], 12);
+            assert_eq!(data.value, 0xFF << 10);
+            assert_eq!(data.mask, 0xFF << 10);
+
+            let data = DataBits::leading(&[], 8);
+            assert_eq!(data.value, 0);
+            assert_eq!(data.mask, 0);
+        }
+    }
 }
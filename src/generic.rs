@@ -12,6 +12,11 @@ impl Instruction {
     }
 }
 
+/// First address of the 2K message RAM.
+pub const RAM_START_ADDRESS: u16 = 0x400;
+/// Size of the message RAM in bytes.
+pub const RAM_SIZE: u16 = 2048;
+
 pub struct OpCode;
 impl OpCode {
     pub const RESET: u16 = 0b0000 << 12;
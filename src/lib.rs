@@ -6,5 +6,10 @@ extern crate bitfield;
 pub mod can;
 pub mod generic;
 pub mod message;
+#[cfg(test)]
+mod mock;
+pub mod mode;
+pub mod ram;
+pub mod recovery;
 pub mod settings;
 pub mod spi;
@@ -2,45 +2,341 @@ type WordSize = u8;
 
 pub const TX_HEADER_SIZE: usize = 8;
 pub const RX_HEADER_SIZE: usize = 12;
-pub const MAX_BUFFER_SIZE: usize = 48;
+pub const MAX_BUFFER_SIZE: usize = 64;
 
-const MESSAGE_IDENTIFIER_MASK: u16 = 0b0000_0111_1111_1111;
-pub type MessageIdentifier = u16;
+/// Largest payload a classic CAN 2.0 frame can carry.
+const CAN_MAX_DATA_LENGTH: usize = 8;
+
+const STANDARD_IDENTIFIER_MASK: u16 = 0b0000_0111_1111_1111;
+const EXTENDED_IDENTIFIER_MASK: u32 = 0x1FFF_FFFF;
+
+/// Number of low bits of an extended identifier that live in the EID field. The upper 11 bits
+/// are the base identifier and go in the SID field.
+const EXTENDED_IDENTIFIER_SHIFT: u32 = 18;
+const EID_MASK: u32 = (1 << EXTENDED_IDENTIFIER_SHIFT) - 1;
+
+/// An 11 bit CAN 2.0A identifier.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct StandardId(u16);
+
+impl StandardId {
+    pub const MAX: StandardId = StandardId(STANDARD_IDENTIFIER_MASK);
+
+    /// Returns None if the value doesn't fit in 11 bits.
+    pub fn new(raw: u16) -> Option<Self> {
+        if raw <= STANDARD_IDENTIFIER_MASK {
+            Some(StandardId(raw))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_raw(self) -> u16 {
+        self.0
+    }
+}
+
+/// A 29 bit CAN 2.0B identifier.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ExtendedId(u32);
+
+impl ExtendedId {
+    pub const MAX: ExtendedId = ExtendedId(EXTENDED_IDENTIFIER_MASK);
+
+    /// Returns None if the value doesn't fit in 29 bits.
+    pub fn new(raw: u32) -> Option<Self> {
+        if raw <= EXTENDED_IDENTIFIER_MASK {
+            Some(ExtendedId(raw))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_raw(self) -> u32 {
+        self.0
+    }
+
+    /// The upper 11 bits, stored in the SID field of a message object.
+    pub fn standard_id(self) -> StandardId {
+        StandardId((self.0 >> EXTENDED_IDENTIFIER_SHIFT) as u16)
+    }
+
+    /// The lower 18 bits, stored in the EID field of a message object.
+    pub fn extension(self) -> u32 {
+        self.0 & EID_MASK
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Id {
+    Standard(StandardId),
+    Extended(ExtendedId),
+}
+
+impl Id {
+    /// Splits the identifier into the (SID, EID, IDE) fields of a message object.
+    pub fn to_fields(self) -> (u16, u32, bool) {
+        match self {
+            Id::Standard(id) => (id.as_raw(), 0, false),
+            Id::Extended(id) => (id.standard_id().as_raw(), id.extension(), true),
+        }
+    }
+
+    /// Rebuilds an identifier from the (SID, EID, IDE) fields of a message object.
+    pub fn from_fields(sid: u16, eid: u32, ide: bool) -> Self {
+        let sid = sid & STANDARD_IDENTIFIER_MASK;
+        if ide {
+            Id::Extended(ExtendedId(
+                ((sid as u32) << EXTENDED_IDENTIFIER_SHIFT) | (eid & EID_MASK),
+            ))
+        } else {
+            Id::Standard(StandardId(sid))
+        }
+    }
+}
+
+impl From<StandardId> for Id {
+    fn from(id: StandardId) -> Self {
+        Id::Standard(id)
+    }
+}
+
+impl From<ExtendedId> for Id {
+    fn from(id: ExtendedId) -> Self {
+        Id::Extended(id)
+    }
+}
+
+impl From<embedded_can::Id> for Id {
+    fn from(id: embedded_can::Id) -> Self {
+        match id {
+            embedded_can::Id::Standard(id) => Id::Standard(StandardId(id.as_raw())),
+            embedded_can::Id::Extended(id) => Id::Extended(ExtendedId(id.as_raw())),
+        }
+    }
+}
+
+impl From<Id> for embedded_can::Id {
+    fn from(id: Id) -> Self {
+        // Both types enforce the same ranges so these can never fail.
+        match id {
+            Id::Standard(id) => {
+                embedded_can::Id::Standard(embedded_can::StandardId::new(id.as_raw()).unwrap())
+            }
+            Id::Extended(id) => {
+                embedded_can::Id::Extended(embedded_can::ExtendedId::new(id.as_raw()).unwrap())
+            }
+        }
+    }
+}
+
+/// Converts a data length code into the number of payload bytes it represents. In classic
+/// CAN, codes above 8 still only carry 8 bytes.
+pub fn dlc_to_length(dlc: u8, fd_frame: bool) -> usize {
+    match (dlc, fd_frame) {
+        (0..=8, _) => dlc as usize,
+        (_, false) => CAN_MAX_DATA_LENGTH,
+        (9, true) => 12,
+        (10, true) => 16,
+        (11, true) => 20,
+        (12, true) => 24,
+        (13, true) => 32,
+        (14, true) => 48,
+        (_, true) => 64,
+    }
+}
+
+/// Converts a payload length into the smallest data length code that can hold it. CAN FD
+/// only supports a handful of lengths above 8, so the payload may need padding up to
+/// `dlc_to_length(dlc, true)` bytes. Lengths above 64 map to the largest code.
+pub fn length_to_dlc(length: usize) -> u8 {
+    match length {
+        0..=8 => length as u8,
+        9..=12 => 9,
+        13..=16 => 10,
+        17..=20 => 11,
+        21..=24 => 12,
+        25..=32 => 13,
+        33..=48 => 14,
+        _ => 15,
+    }
+}
+
+/// Message RAM is accessed a word at a time, so payloads are padded to a multiple of 4 bytes.
+fn padded_length(length: usize) -> usize {
+    (length + 3) & !3
+}
 
 bitfield! {
     pub struct TxHeader([WordSize]);
     impl Debug;
     u8;
     // T0
-    u16, standard_identifier, set_standard_identifier: 10, 0;
-    u32, extended_identifier, set_extended_identifier: 28, 10;
-    sid11, _: 29, 29;
+    pub u16, standard_identifier, set_standard_identifier: 10, 0;
+    pub u32, extended_identifier, set_extended_identifier: 28, 11;
+    pub sid11, set_sid11: 29;
     // T1
     pub data_length_code, set_data_length_code: 35, 32;
-    pub identifier_extension, _: 36;
-    pub remote_transmission_request, _: 37;
-    pub bit_rate_switched, _: 38;
-    pub fd_frame, _: 39;
-    pub error_status_indicator, _: 40;
+    pub identifier_extension, set_identifier_extension: 36;
+    pub remote_transmission_request, set_remote_transmission_request: 37;
+    pub bit_rate_switched, set_bit_rate_switched: 38;
+    pub fd_frame, set_fd_frame: 39;
+    pub error_status_indicator, set_error_status_indicator: 40;
     pub sequence, set_sequence: 47, 41;
 }
 
+impl TxHeader<[WordSize; TX_HEADER_SIZE]> {
+    pub fn identifier(&self) -> Id {
+        Id::from_fields(
+            self.standard_identifier(),
+            self.extended_identifier(),
+            self.identifier_extension(),
+        )
+    }
+
+    /// Writes the SID, EID and IDE fields for the given identifier.
+    pub fn set_identifier(&mut self, identifier: Id) {
+        let (sid, eid, ide) = identifier.to_fields();
+        self.set_standard_identifier(sid);
+        self.set_extended_identifier(eid);
+        self.set_identifier_extension(ide);
+    }
+}
+
 pub struct TransmitMessage {
     header: TxHeader<[WordSize; TX_HEADER_SIZE]>,
     data: [WordSize; MAX_BUFFER_SIZE],
 }
 impl TransmitMessage {
-    pub fn new(identifier: MessageIdentifier, data: [WordSize; MAX_BUFFER_SIZE]) -> Self {
+    /// Creates a new message with the given identifier. Any data beyond the 8 bytes a
+    /// classic CAN frame can carry is discarded.
+    pub fn new<I: Into<Id>>(identifier: I, data: &[WordSize]) -> Self {
+        let mut header = TxHeader([0; TX_HEADER_SIZE]);
+        header.set_identifier(identifier.into());
+
+        let length = core::cmp::min(data.len(), CAN_MAX_DATA_LENGTH);
+        header.set_data_length_code(length as u8);
+
+        let mut buffer = [0; MAX_BUFFER_SIZE];
+        buffer[..length].copy_from_slice(&data[..length]);
+
+        TransmitMessage {
+            header,
+            data: buffer,
+        }
+    }
+
+    /// Creates a new CAN FD message with the given identifier. Up to 64 bytes of data are
+    /// kept; if the length isn't one CAN FD supports the payload is padded with zeros up to
+    /// the next valid length.
+    pub fn new_fd<I: Into<Id>>(identifier: I, data: &[WordSize]) -> Self {
+        let mut header = TxHeader([0; TX_HEADER_SIZE]);
+        header.set_identifier(identifier.into());
+        header.set_fd_frame(true);
+
+        let length = core::cmp::min(data.len(), MAX_BUFFER_SIZE);
+        header.set_data_length_code(length_to_dlc(length));
+
+        let mut buffer = [0; MAX_BUFFER_SIZE];
+        buffer[..length].copy_from_slice(&data[..length]);
+
+        TransmitMessage {
+            header,
+            data: buffer,
+        }
+    }
+
+    /// Creates a remote frame requesting `length` bytes from the node that owns the
+    /// identifier. Remote frames carry no data and only exist in classic CAN, so the
+    /// requested length is capped at 8.
+    pub fn new_remote<I: Into<Id>>(identifier: I, length: usize) -> Self {
         let mut header = TxHeader([0; TX_HEADER_SIZE]);
-        header.set_standard_identifier(identifier & MESSAGE_IDENTIFIER_MASK);
+        header.set_identifier(identifier.into());
+        header.set_remote_transmission_request(true);
+        header.set_data_length_code(core::cmp::min(length, CAN_MAX_DATA_LENGTH) as u8);
+
         TransmitMessage {
-            header: header,
-            data: data,
+            header,
+            data: [0; MAX_BUFFER_SIZE],
         }
     }
 
-    pub fn bytes(self) -> (usize, [WordSize; TX_HEADER_SIZE + MAX_BUFFER_SIZE]) {
-        (0, [0u8; TX_HEADER_SIZE + MAX_BUFFER_SIZE])
+    /// Sets the FDF bit. Clearing it on a message with more than 8 bytes of data truncates
+    /// the payload to 8 bytes.
+    pub fn with_fd_frame(mut self, enabled: bool) -> Self {
+        self.header.set_fd_frame(enabled);
+        self
+    }
+
+    /// Sets the BRS bit so the data phase is sent at the data bit rate. Only meaningful for
+    /// CAN FD frames.
+    pub fn with_bit_rate_switch(mut self, enabled: bool) -> Self {
+        self.header.set_bit_rate_switched(enabled);
+        self
+    }
+
+    /// Sets the ESI bit. The controller only transmits it as set when C1CON.ESIGM is set,
+    /// otherwise it reflects the node's own error state.
+    pub fn with_error_status_indicator(mut self, enabled: bool) -> Self {
+        self.header.set_error_status_indicator(enabled);
+        self
+    }
+
+    pub fn header(&self) -> &TxHeader<[WordSize; TX_HEADER_SIZE]> {
+        &self.header
+    }
+
+    pub fn fd_frame(&self) -> bool {
+        self.header.fd_frame()
+    }
+
+    pub fn bit_rate_switched(&self) -> bool {
+        self.header.bit_rate_switched()
+    }
+
+    pub fn error_status_indicator(&self) -> bool {
+        self.header.error_status_indicator()
+    }
+
+    pub fn remote_transmission_request(&self) -> bool {
+        self.header.remote_transmission_request()
+    }
+
+    pub fn identifier(&self) -> Id {
+        self.header.identifier()
+    }
+
+    pub fn data_length_code(&self) -> u8 {
+        self.header.data_length_code()
+    }
+
+    /// Number of payload bytes this message carries, based on its DLC. Always 0 for remote
+    /// frames, whose DLC is the length being requested.
+    pub fn length(&self) -> usize {
+        if self.header.remote_transmission_request() {
+            0
+        } else {
+            dlc_to_length(self.header.data_length_code(), self.header.fd_frame())
+        }
+    }
+
+    pub fn data(&self) -> &[WordSize] {
+        &self.data[..self.length()]
+    }
+
+    /// Serializes the message into the layout expected in a TX FIFO object: the T0 and T1
+    /// header words in little-endian order followed by the payload, padded with zeros to a
+    /// multiple of 4 bytes. Returns the number of valid bytes along with the buffer.
+    pub fn bytes(&self) -> (usize, [WordSize; TX_HEADER_SIZE + MAX_BUFFER_SIZE]) {
+        let mut buffer = [0u8; TX_HEADER_SIZE + MAX_BUFFER_SIZE];
+
+        // The header bitfield is stored LSB first so it is already in little-endian order.
+        buffer[..TX_HEADER_SIZE].copy_from_slice(&self.header.0);
+
+        let length = self.length();
+        buffer[TX_HEADER_SIZE..TX_HEADER_SIZE + length].copy_from_slice(&self.data[..length]);
+
+        (TX_HEADER_SIZE + padded_length(length), buffer)
     }
 }
 
@@ -50,8 +346,8 @@ bitfield! {
     u8;
     // T0
     pub u16, standard_identifier, set_standard_identifier: 10, 0;
-    pub u32, extended_identifier, set_extended_identifier: 28, 10;
-    sid11, _: 29, 29;
+    pub u32, extended_identifier, set_extended_identifier: 28, 11;
+    pub sid11, _: 29;
     // T1
     pub data_length_code, set_data_length_code: 35, 32;
     pub identifier_extension, _: 36;
@@ -64,8 +360,379 @@ bitfield! {
     pub u32, timestamp, _: 95, 64;
 }
 
+impl RxHeader<[WordSize; RX_HEADER_SIZE]> {
+    pub fn identifier(&self) -> Id {
+        Id::from_fields(
+            self.standard_identifier(),
+            self.extended_identifier(),
+            self.identifier_extension(),
+        )
+    }
+}
+
+/// Size of the R0 and R1 header words, which are always present on a received object.
+const RX_HEADER_SIZE_NO_TIMESTAMP: usize = 8;
+
+pub enum Error {
+    /// The buffer handed in was shorter than the object it should contain. Holds the number
+    /// of bytes that were needed.
+    BufferTooShort(usize),
+}
+
 pub struct ReceiveMessage {
     header: RxHeader<[WordSize; RX_HEADER_SIZE]>,
+    has_timestamp: bool,
     data: [WordSize; MAX_BUFFER_SIZE],
 }
-impl ReceiveMessage {}
+impl ReceiveMessage {
+    /// Parses a message object as read out of an RX FIFO. `timestamp_enabled` must match the
+    /// FIFO's RXTSEN bit, as it decides whether the header is 8 or 12 bytes long.
+    pub fn from_bytes(bytes: &[WordSize], timestamp_enabled: bool) -> Result<Self, Error> {
+        let header_size = ReceiveMessage::header_size(timestamp_enabled);
+        if bytes.len() < header_size {
+            return Err(Error::BufferTooShort(header_size));
+        }
+
+        let mut header = RxHeader([0; RX_HEADER_SIZE]);
+        header.0[..header_size].copy_from_slice(&bytes[..header_size]);
+
+        let length = if header.remote_transmission_request() {
+            0
+        } else {
+            dlc_to_length(header.data_length_code(), header.fd_frame())
+        };
+        if bytes.len() < header_size + length {
+            return Err(Error::BufferTooShort(header_size + length));
+        }
+
+        let mut data = [0; MAX_BUFFER_SIZE];
+        data[..length].copy_from_slice(&bytes[header_size..header_size + length]);
+
+        Ok(ReceiveMessage {
+            header,
+            has_timestamp: timestamp_enabled,
+            data,
+        })
+    }
+
+    /// Size in bytes of the header of a received object.
+    pub fn header_size(timestamp_enabled: bool) -> usize {
+        if timestamp_enabled {
+            RX_HEADER_SIZE
+        } else {
+            RX_HEADER_SIZE_NO_TIMESTAMP
+        }
+    }
+
+    pub fn header(&self) -> &RxHeader<[WordSize; RX_HEADER_SIZE]> {
+        &self.header
+    }
+
+    pub fn identifier(&self) -> Id {
+        self.header.identifier()
+    }
+
+    pub fn data_length_code(&self) -> u8 {
+        self.header.data_length_code()
+    }
+
+    /// Number of payload bytes this message carries, based on its DLC. Always 0 for remote
+    /// frames, whose DLC is the length being requested.
+    pub fn length(&self) -> usize {
+        if self.remote_transmission_request() {
+            0
+        } else {
+            dlc_to_length(self.data_length_code(), self.fd_frame())
+        }
+    }
+
+    pub fn remote_transmission_request(&self) -> bool {
+        self.header.remote_transmission_request()
+    }
+
+    pub fn identifier_extension(&self) -> bool {
+        self.header.identifier_extension()
+    }
+
+    pub fn bit_rate_switched(&self) -> bool {
+        self.header.bit_rate_switched()
+    }
+
+    pub fn fd_frame(&self) -> bool {
+        self.header.fd_frame()
+    }
+
+    pub fn error_status_indicator(&self) -> bool {
+        self.header.error_status_indicator()
+    }
+
+    /// Number of the filter that accepted this message.
+    pub fn filter_hit(&self) -> u8 {
+        self.header.filter_hit()
+    }
+
+    /// Timestamp captured by the time base counter, if the FIFO had timestamps enabled.
+    pub fn timestamp(&self) -> Option<u32> {
+        if self.has_timestamp {
+            Some(self.header.timestamp())
+        } else {
+            None
+        }
+    }
+
+    pub fn data(&self) -> &[WordSize] {
+        &self.data[..self.length()]
+    }
+}
+
+impl From<ReceiveMessage> for TransmitMessage {
+    /// Turns a received message back into one that can be sent, e.g. for forwarding. The
+    /// timestamp and filter hit are dropped.
+    fn from(message: ReceiveMessage) -> Self {
+        // R0 and R1 match T0 and T1 apart from FILHIT, which sits where SEQ goes.
+        let mut header = TxHeader([0; TX_HEADER_SIZE]);
+        header
+            .0
+            .copy_from_slice(&message.header.0[..RX_HEADER_SIZE_NO_TIMESTAMP]);
+        header.set_sequence(0);
+
+        TransmitMessage {
+            header,
+            data: message.data,
+        }
+    }
+}
+
+impl embedded_can::Frame for TransmitMessage {
+    /// Creates a classic CAN data frame. Returns None if there are more than 8 bytes of data.
+    fn new(id: impl Into<embedded_can::Id>, data: &[u8]) -> Option<Self> {
+        if data.len() > CAN_MAX_DATA_LENGTH {
+            return None;
+        }
+        Some(TransmitMessage::new(Id::from(id.into()), data))
+    }
+
+    fn new_remote(id: impl Into<embedded_can::Id>, dlc: usize) -> Option<Self> {
+        if dlc > CAN_MAX_DATA_LENGTH {
+            return None;
+        }
+        Some(TransmitMessage::new_remote(Id::from(id.into()), dlc))
+    }
+
+    fn is_extended(&self) -> bool {
+        self.header.identifier_extension()
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.remote_transmission_request()
+    }
+
+    fn id(&self) -> embedded_can::Id {
+        self.identifier().into()
+    }
+
+    fn dlc(&self) -> usize {
+        self.data_length_code() as usize
+    }
+
+    fn data(&self) -> &[u8] {
+        TransmitMessage::data(self)
+    }
+}
+
+impl embedded_can::Frame for ReceiveMessage {
+    /// Creates a classic CAN data frame as it would have been received, without a timestamp.
+    /// Returns None if there are more than 8 bytes of data.
+    fn new(id: impl Into<embedded_can::Id>, data: &[u8]) -> Option<Self> {
+        let message: TransmitMessage = embedded_can::Frame::new(id, data)?;
+        let (length, bytes) = message.bytes();
+        ReceiveMessage::from_bytes(&bytes[..length], false).ok()
+    }
+
+    fn new_remote(id: impl Into<embedded_can::Id>, dlc: usize) -> Option<Self> {
+        let message: TransmitMessage = embedded_can::Frame::new_remote(id, dlc)?;
+        let (length, bytes) = message.bytes();
+        ReceiveMessage::from_bytes(&bytes[..length], false).ok()
+    }
+
+    fn is_extended(&self) -> bool {
+        self.identifier_extension()
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.remote_transmission_request()
+    }
+
+    fn id(&self) -> embedded_can::Id {
+        self.identifier().into()
+    }
+
+    fn dlc(&self) -> usize {
+        self.data_length_code() as usize
+    }
+
+    fn data(&self) -> &[u8] {
+        ReceiveMessage::data(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn standard(id: u16) -> StandardId {
+        StandardId::new(id).unwrap()
+    }
+
+    fn extended(id: u32) -> ExtendedId {
+        ExtendedId::new(id).unwrap()
+    }
+
+    fn parse(bytes: &[WordSize], timestamp_enabled: bool) -> ReceiveMessage {
+        match ReceiveMessage::from_bytes(bytes, timestamp_enabled) {
+            Ok(message) => message,
+            Err(Error::BufferTooShort(needed)) => panic!("buffer too short, needed {}", needed),
+        }
+    }
+
+    #[test]
+    fn standard_frame_serializes_header_and_padded_payload() {
+        let message = TransmitMessage::new(standard(0x123), &[1, 2, 3, 4, 5]);
+        let (length, bytes) = message.bytes();
+
+        assert_eq!(length, 16);
+        assert_eq!(
+            bytes[..length],
+            [0x23, 0x01, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 1, 2, 3, 4, 5, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn empty_frame_is_only_a_header() {
+        let (length, bytes) = TransmitMessage::new(standard(0x7FF), &[]).bytes();
+
+        assert_eq!(length, TX_HEADER_SIZE);
+        assert_eq!(bytes[..length], [0xFF, 0x07, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn header_size_depends_on_timestamp() {
+        let mut bytes = [0u8; 16];
+        // R0: SID 0x123, R1: DLC 2, R2: timestamp 0x12345678.
+        bytes[..4].copy_from_slice(&0x123u32.to_le_bytes());
+        bytes[4..8].copy_from_slice(&2u32.to_le_bytes());
+        bytes[8..12].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        bytes[12..14].copy_from_slice(&[0xAA, 0xBB]);
+
+        let message = parse(&bytes, true);
+        assert_eq!(message.timestamp(), Some(0x1234_5678));
+        assert_eq!(message.data(), &[0xAA, 0xBB]);
+
+        let message = parse(&bytes[..10], false);
+        assert_eq!(message.timestamp(), None);
+        assert_eq!(message.data(), &[0x78, 0x56]);
+
+        assert_eq!(ReceiveMessage::header_size(true), 12);
+        assert_eq!(ReceiveMessage::header_size(false), 8);
+    }
+
+    #[test]
+    fn short_buffers_are_rejected() {
+        let mut bytes = [0u8; 12];
+        bytes[4] = 8;
+
+        match ReceiveMessage::from_bytes(&bytes[..7], false) {
+            Err(Error::BufferTooShort(8)) => (),
+            _ => panic!("expected the header to be too short"),
+        }
+        match ReceiveMessage::from_bytes(&bytes[..11], true) {
+            Err(Error::BufferTooShort(12)) => (),
+            _ => panic!("expected the header to be too short"),
+        }
+        match ReceiveMessage::from_bytes(&bytes, false) {
+            Err(Error::BufferTooShort(16)) => (),
+            _ => panic!("expected the payload to be too short"),
+        }
+    }
+
+    #[test]
+    fn extended_identifier_is_split_into_sid_and_eid() {
+        let id = 0x1ABC_DEF5;
+        let (sid, eid, ide) = Id::from(extended(id)).to_fields();
+        assert_eq!(sid as u32, id >> 18);
+        assert_eq!(eid, id & 0x3FFFF);
+        assert!(ide);
+        assert_eq!(Id::from_fields(sid, eid, ide), Id::from(extended(id)));
+
+        // The EID field sits in bits 28:11 of T0, right above the SID.
+        let (_, bytes) = TransmitMessage::new(extended(id), &[]).bytes();
+        let t0 = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        assert_eq!(t0 & 0x7FF, id >> 18);
+        assert_eq!((t0 >> 11) & 0x3FFFF, id & 0x3FFFF);
+        assert_eq!(bytes[4] & 0x10, 0x10);
+    }
+
+    #[test]
+    fn standard_identifier_has_no_extension() {
+        assert_eq!(Id::from(standard(0x555)).to_fields(), (0x555, 0, false));
+        assert_eq!(
+            Id::from_fields(0x555, 0x3FFFF, false),
+            Id::from(standard(0x555))
+        );
+    }
+
+    #[test]
+    fn fd_lengths_map_to_and_from_dlc() {
+        let lengths = [12, 16, 20, 24, 32, 48, 64];
+        for (dlc, &length) in (9..=15).zip(lengths.iter()) {
+            assert_eq!(dlc_to_length(dlc, true), length);
+            assert_eq!(dlc_to_length(dlc, false), 8);
+            assert_eq!(length_to_dlc(length), dlc);
+            assert_eq!(length_to_dlc(length - 1), dlc);
+        }
+        for length in 0..=8 {
+            assert_eq!(length_to_dlc(length), length as u8);
+            assert_eq!(dlc_to_length(length as u8, true), length);
+        }
+        assert_eq!(length_to_dlc(65), 15);
+    }
+
+    #[test]
+    fn fd_payload_is_padded_with_zeros() {
+        let data = [0xFF; 10];
+        let message = TransmitMessage::new_fd(standard(0x100), &data);
+        assert_eq!(message.length(), 12);
+
+        let (length, bytes) = message.bytes();
+        assert_eq!(length, TX_HEADER_SIZE + 12);
+        // FDF and a DLC of 9.
+        assert_eq!(bytes[4], 0x80 | 9);
+        assert_eq!(bytes[TX_HEADER_SIZE..TX_HEADER_SIZE + 10], data);
+        assert_eq!(bytes[TX_HEADER_SIZE + 10..length], [0, 0]);
+    }
+
+    #[test]
+    fn remote_frame_has_no_payload() {
+        let message = TransmitMessage::new_remote(standard(0x321), 4);
+        assert_eq!(message.data_length_code(), 4);
+        assert_eq!(message.length(), 0);
+
+        let (length, bytes) = message.bytes();
+        assert_eq!(length, TX_HEADER_SIZE);
+        assert_eq!(bytes[4], 0x20 | 4);
+    }
+
+    #[test]
+    fn received_remote_frame_has_length_zero() {
+        let mut bytes = [0u8; 8];
+        bytes[..4].copy_from_slice(&0x321u32.to_le_bytes());
+        // RTR with a requested length of 8.
+        bytes[4] = 0x20 | 8;
+
+        let message = parse(&bytes, false);
+        assert!(message.remote_transmission_request());
+        assert_eq!(message.data_length_code(), 8);
+        assert_eq!(message.length(), 0);
+        assert!(message.data().is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+//! A stand-in for the MCP2517FD on the other end of the SPI bus, for unit tests.
+
+use core::convert::Infallible;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::{OutputPin, StatefulOutputPin};
+
+use crate::can::control::OperationMode;
+use crate::can::interrupt::C1INT;
+use crate::generic::{Instruction, OpCode, SFRAddress};
+use crate::spi::Controller;
+
+/// Covers the SFRs, the message RAM and the OSC/IOCON block.
+const ADDRESS_SPACE: usize = 0x1000;
+
+/// Keeps every register and RAM byte in one flat memory. Requested modes are entered right
+/// away, Sleep turns the oscillator off until OSCDIS is cleared and C1INT flags behave as on
+/// the real thing. Other registers just store what is written.
+pub struct Device {
+    memory: [u8; ADDRESS_SPACE],
+    /// Instruction and address sent at the start of the current transaction.
+    pending: Option<(u16, usize)>,
+}
+
+impl Device {
+    pub fn new() -> Self {
+        Device {
+            memory: [0; ADDRESS_SPACE],
+            pending: None,
+        }
+    }
+
+    pub fn sfr(&self, address: SFRAddress) -> u32 {
+        self.word(address as usize)
+    }
+
+    fn word(&self, address: usize) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&self.memory[address..address + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Writes a value without any of the side effects a write over SPI has.
+    pub fn set_sfr(&mut self, address: SFRAddress, value: u32) {
+        let address = address as usize;
+        self.memory[address..address + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn ram(&mut self, address: u16) -> &mut [u8] {
+        &mut self.memory[address as usize..]
+    }
+
+    /// Applies the side effects of writing the SFR at `address`, which held `previous`.
+    fn written(&mut self, address: usize, previous: u32) {
+        if address == SFRAddress::C1INT as usize {
+            // Flags are only cleared by writing 0, the others are read only.
+            let written = self.sfr(SFRAddress::C1INT);
+            let flags = previous & 0xFFFF & (written | !C1INT::clearable_flags());
+            self.set_sfr(SFRAddress::C1INT, (written & 0xFFFF_0000) | flags);
+        } else if address == SFRAddress::C1CON as usize {
+            // Copy REQOP into OPMOD.
+            let c1con = self.sfr(SFRAddress::C1CON);
+            let mode = (c1con >> 24) & 0b111;
+            self.set_sfr(SFRAddress::C1CON, (c1con & !(0b111 << 21)) | (mode << 21));
+            if mode == OperationMode::Sleep as u32 {
+                // Sleep turns the oscillator off.
+                let osc = self.sfr(SFRAddress::OSC);
+                self.set_sfr(SFRAddress::OSC, (osc | (1 << 2)) & !(1 << 10));
+            }
+        } else if address == SFRAddress::OSC as usize {
+            // OSCRDY follows OSCDIS.
+            let osc = self.sfr(SFRAddress::OSC);
+            let ready = if osc & (1 << 2) == 0 { 1 << 10 } else { 0 };
+            self.set_sfr(SFRAddress::OSC, (osc & !(1 << 10)) | ready);
+        }
+    }
+}
+
+impl Write<u8> for Device {
+    type Error = Infallible;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        match self.pending.take() {
+            None => {
+                let instruction = Instruction(u16::from_be_bytes([words[0], words[1]]));
+                if instruction.op_code() << 12 != OpCode::RESET {
+                    self.pending =
+                        Some((instruction.op_code() << 12, instruction.address() as usize));
+                }
+            }
+            Some((op_code, address)) => {
+                assert_eq!(op_code, OpCode::WRITE);
+                // Side effects only matter for the SFRs, which are written a word at a time.
+                let previous = self.word(address);
+                self.memory[address..address + words.len()].copy_from_slice(words);
+                self.written(address, previous);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transfer<u8> for Device {
+    type Error = Infallible;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        let (op_code, address) = self.pending.take().unwrap();
+        assert_eq!(op_code, OpCode::READ);
+        words.copy_from_slice(&self.memory[address..address + words.len()]);
+        Ok(words)
+    }
+}
+
+/// A slave select line that goes nowhere.
+pub struct Pin(bool);
+
+impl OutputPin for Pin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0 = false;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0 = true;
+        Ok(())
+    }
+}
+
+impl StatefulOutputPin for Pin {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.0)
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.0)
+    }
+}
+
+pub struct Delay;
+
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, _us: u32) {}
+}
+
+pub fn controller(device: Device) -> Controller<Device, Pin> {
+    Controller::new(device, Pin(true))
+}
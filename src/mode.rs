@@ -0,0 +1,429 @@
+use core::marker::PhantomData;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::{OutputPin, StatefulOutputPin};
+
+use crate::can;
+use crate::can::control::OperationMode;
+use crate::message;
+use crate::settings;
+use crate::spi;
+
+/// An operation mode the typestate Controller can be in.
+pub trait Mode {
+    const OPERATION_MODE: OperationMode;
+}
+
+/// Modes in which the controller puts frames on the bus.
+pub trait Transmitting: Mode {}
+
+/// Modes in which the controller stores frames it sees on the bus.
+pub trait Receiving: Mode {}
+
+/// Every mode but Sleep, which has to wake the oscillator before anything else.
+pub trait Awake: Mode {}
+
+pub struct Configuration;
+pub struct NormalCanFD;
+pub struct NormalCan2;
+pub struct ListenOnly;
+pub struct InternalLoopback;
+pub struct ExternalLoopback;
+pub struct Restricted;
+pub struct Sleep;
+
+impl Mode for Configuration {
+    const OPERATION_MODE: OperationMode = OperationMode::Configuration;
+}
+
+impl Mode for NormalCanFD {
+    const OPERATION_MODE: OperationMode = OperationMode::NormalCanFD;
+}
+
+impl Mode for NormalCan2 {
+    const OPERATION_MODE: OperationMode = OperationMode::NormalCan2;
+}
+
+impl Mode for ListenOnly {
+    const OPERATION_MODE: OperationMode = OperationMode::ListenOnly;
+}
+
+impl Mode for InternalLoopback {
+    const OPERATION_MODE: OperationMode = OperationMode::InternalLoopback;
+}
+
+impl Mode for ExternalLoopback {
+    const OPERATION_MODE: OperationMode = OperationMode::ExternalLoopback;
+}
+
+impl Mode for Restricted {
+    const OPERATION_MODE: OperationMode = OperationMode::Restricted;
+}
+
+impl Mode for Sleep {
+    const OPERATION_MODE: OperationMode = OperationMode::Sleep;
+}
+
+impl Transmitting for NormalCanFD {}
+impl Transmitting for NormalCan2 {}
+impl Transmitting for InternalLoopback {}
+impl Transmitting for ExternalLoopback {}
+
+impl Awake for Configuration {}
+impl Awake for NormalCanFD {}
+impl Awake for NormalCan2 {}
+impl Awake for ListenOnly {}
+impl Awake for InternalLoopback {}
+impl Awake for ExternalLoopback {}
+impl Awake for Restricted {}
+
+impl Receiving for NormalCanFD {}
+impl Receiving for NormalCan2 {}
+impl Receiving for ListenOnly {}
+impl Receiving for InternalLoopback {}
+impl Receiving for ExternalLoopback {}
+impl Receiving for Restricted {}
+
+/// A failed transition, handing back the controller in the state it was in.
+pub struct TransitionError<C> {
+    pub controller: C,
+    pub error: spi::Error,
+}
+
+/// spi::Controller with its operation mode tracked in the type, so that configuration
+/// only operations can only be called in Configuration mode and transmitting can only be
+/// done in modes that put frames on the bus.
+///
+/// Transitions wait up to `timeout_us` microseconds for the mode change, see
+/// spi::Controller::set_mode. If it doesn't happen the previous mode is requested again
+/// and the controller is handed back in its previous state.
+pub struct Controller<T, SS, M> {
+    inner: spi::Controller<T, SS>,
+    mode: PhantomData<M>,
+}
+
+impl<T, SS> Controller<T, SS, Configuration>
+where
+    T: Write<u8> + Transfer<u8>,
+    SS: StatefulOutputPin,
+    <SS as OutputPin>::Error: core::fmt::Debug,
+{
+    /// Puts the controller in Configuration mode, whatever mode it was in.
+    pub fn enter<D: DelayUs<u32>>(
+        mut inner: spi::Controller<T, SS>,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<Self, TransitionError<spi::Controller<T, SS>>> {
+        match inner.set_mode(OperationMode::Configuration, delay, timeout_us) {
+            Ok(()) => Ok(Controller {
+                inner,
+                mode: PhantomData,
+            }),
+            Err(error) => Err(TransitionError {
+                controller: inner,
+                error,
+            }),
+        }
+    }
+
+    /// Same as spi::Controller::configure, except that the controller stays in
+    /// Configuration mode whatever Settings::operation_mode says.
+    pub fn configure<D: DelayUs<u32>>(
+        &mut self,
+        mut settings: settings::Settings,
+        delay: &mut D,
+    ) -> Result<(), spi::ConfigError> {
+        settings.operation_mode = OperationMode::Configuration;
+        self.inner.configure(settings, delay)
+    }
+
+    pub fn configure_fifo_control<F>(&mut self, fifo_number: u8, f: F) -> Result<(), spi::Error>
+    where
+        F: FnOnce(&mut can::fifo::ControlRegister) -> &mut can::fifo::ControlRegister,
+    {
+        self.inner.configure_fifo_control(fifo_number, f)
+    }
+
+    pub fn enable_transmit_event_fifo(&mut self, object_count: u8) -> Result<(), spi::Error> {
+        self.inner.enable_transmit_event_fifo(object_count)
+    }
+
+    pub fn configure_wakeup(
+        &mut self,
+        filter: Option<can::control::WakeupFilterTime>,
+        interrupt: bool,
+    ) -> Result<(), spi::Error> {
+        self.inner.configure_wakeup(filter, interrupt)
+    }
+
+    pub fn into_normal_can_fd<D: DelayUs<u32>>(
+        self,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<Controller<T, SS, NormalCanFD>, TransitionError<Self>> {
+        self.transition(delay, timeout_us)
+    }
+
+    pub fn into_normal_can2<D: DelayUs<u32>>(
+        self,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<Controller<T, SS, NormalCan2>, TransitionError<Self>> {
+        self.transition(delay, timeout_us)
+    }
+
+    pub fn into_listen_only<D: DelayUs<u32>>(
+        self,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<Controller<T, SS, ListenOnly>, TransitionError<Self>> {
+        self.transition(delay, timeout_us)
+    }
+
+    pub fn into_internal_loopback<D: DelayUs<u32>>(
+        self,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<Controller<T, SS, InternalLoopback>, TransitionError<Self>> {
+        self.transition(delay, timeout_us)
+    }
+
+    pub fn into_external_loopback<D: DelayUs<u32>>(
+        self,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<Controller<T, SS, ExternalLoopback>, TransitionError<Self>> {
+        self.transition(delay, timeout_us)
+    }
+
+    pub fn into_restricted<D: DelayUs<u32>>(
+        self,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<Controller<T, SS, Restricted>, TransitionError<Self>> {
+        self.transition(delay, timeout_us)
+    }
+
+    pub fn into_sleep<D: DelayUs<u32>>(
+        mut self,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<Controller<T, SS, Sleep>, TransitionError<Self>> {
+        match self.inner.sleep(delay, timeout_us) {
+            Ok(()) => Ok(Controller {
+                inner: self.inner,
+                mode: PhantomData,
+            }),
+            Err(error) => Err(TransitionError {
+                controller: self,
+                error,
+            }),
+        }
+    }
+}
+
+impl<T, SS> Controller<T, SS, Sleep>
+where
+    T: Write<u8> + Transfer<u8>,
+    SS: StatefulOutputPin,
+    <SS as OutputPin>::Error: core::fmt::Debug,
+{
+    /// Wakes the controller and waits for its oscillator, see spi::Controller::wake.
+    pub fn wake<D: DelayUs<u32>>(
+        mut self,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<Controller<T, SS, Configuration>, TransitionError<Self>> {
+        match self.inner.wake(delay, timeout_us) {
+            Ok(()) => Ok(Controller {
+                inner: self.inner,
+                mode: PhantomData,
+            }),
+            Err(error) => Err(TransitionError {
+                controller: self,
+                error,
+            }),
+        }
+    }
+}
+
+impl<T, SS, M> Controller<T, SS, M>
+where
+    T: Write<u8> + Transfer<u8>,
+    SS: StatefulOutputPin,
+    <SS as OutputPin>::Error: core::fmt::Debug,
+    M: Mode,
+{
+    fn transition<N: Mode, D: DelayUs<u32>>(
+        mut self,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<Controller<T, SS, N>, TransitionError<Self>> {
+        match self.inner.set_mode(N::OPERATION_MODE, delay, timeout_us) {
+            Ok(()) => Ok(Controller {
+                inner: self.inner,
+                mode: PhantomData,
+            }),
+            Err(error) => {
+                // Best effort, the error that matters is the one already at hand.
+                let _ = self.inner.request_mode(M::OPERATION_MODE);
+                Err(TransitionError {
+                    controller: self,
+                    error,
+                })
+            }
+        }
+    }
+
+    /// Reads back the mode the controller is actually in. This can differ from M when the
+    /// controller changed mode on its own, e.g. to Restricted after a system error.
+    pub fn operation_mode(&mut self) -> Result<OperationMode, spi::Error> {
+        self.inner.operation_mode()
+    }
+
+    pub fn bus_state(&mut self) -> Result<can::bus::BusStatus, spi::Error> {
+        self.inner.bus_state()
+    }
+
+    pub fn diagnostics(&mut self) -> Result<can::bus::Diagnostics, spi::Error> {
+        self.inner.diagnostics()
+    }
+
+    pub fn clear_diagnostics(&mut self) -> Result<(), spi::Error> {
+        self.inner.clear_diagnostics()
+    }
+
+    pub fn read_interrupts(&mut self) -> Result<can::interrupt::C1INT, spi::Error> {
+        self.inner.read_interrupts()
+    }
+
+    pub fn modify_interrupts<F>(&mut self, f: F) -> Result<(), spi::Error>
+    where
+        F: FnOnce(can::interrupt::C1INT) -> can::interrupt::C1INT,
+    {
+        self.inner.modify_interrupts(f)
+    }
+
+    pub fn service_interrupts(&mut self) -> Result<can::interrupt::Events, spi::Error> {
+        self.inner.service_interrupts()
+    }
+
+    pub fn read_interrupt_vector(&mut self) -> Result<can::interrupt::C1VEC, spi::Error> {
+        self.inner.read_interrupt_vector()
+    }
+
+    pub fn read_time_base_counter(&mut self) -> Result<u32, spi::Error> {
+        self.inner.read_time_base_counter()
+    }
+
+    pub fn transmitter_delay_value(&mut self) -> Result<u8, spi::Error> {
+        self.inner.transmitter_delay_value()
+    }
+
+    pub fn read_fifo_status(
+        &mut self,
+        fifo_number: u8,
+    ) -> Result<can::fifo::StatusRegister, spi::Error> {
+        self.inner.read_fifo_status(fifo_number)
+    }
+
+    pub fn set_filter(
+        &mut self,
+        filter_number: u8,
+        filter: &can::filter::Filter,
+    ) -> Result<(), spi::Error> {
+        self.inner.set_filter(filter_number, filter)
+    }
+
+    pub fn enable_filter(&mut self, filter_number: u8) -> Result<(), spi::Error> {
+        self.inner.enable_filter(filter_number)
+    }
+
+    pub fn disable_filter(&mut self, filter_number: u8) -> Result<(), spi::Error> {
+        self.inner.disable_filter(filter_number)
+    }
+
+    pub fn apply_filters(
+        &mut self,
+        compiled: &can::filter::CompiledFilters,
+    ) -> Result<(), spi::Error> {
+        self.inner.apply_filters(compiled)
+    }
+
+    /// Gives back the untyped controller, which no longer checks anything at compile time.
+    pub fn free(self) -> spi::Controller<T, SS> {
+        self.inner
+    }
+}
+
+impl<T, SS, M> Controller<T, SS, M>
+where
+    T: Write<u8> + Transfer<u8>,
+    SS: StatefulOutputPin,
+    <SS as OutputPin>::Error: core::fmt::Debug,
+    M: Awake,
+{
+    pub fn into_configuration<D: DelayUs<u32>>(
+        self,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<Controller<T, SS, Configuration>, TransitionError<Self>> {
+        self.transition(delay, timeout_us)
+    }
+}
+
+impl<T, SS, M> Controller<T, SS, M>
+where
+    T: Write<u8> + Transfer<u8>,
+    SS: StatefulOutputPin,
+    <SS as OutputPin>::Error: core::fmt::Debug,
+    M: Transmitting,
+{
+    pub fn transmit(
+        &mut self,
+        fifo_number: u8,
+        message: &message::TransmitMessage,
+    ) -> Result<(), spi::Error> {
+        self.inner.transmit(fifo_number, message)
+    }
+
+    pub fn try_transmit(
+        &mut self,
+        fifo_number: u8,
+        message: &message::TransmitMessage,
+    ) -> nb::Result<(), spi::Error> {
+        self.inner.try_transmit(fifo_number, message)
+    }
+}
+
+impl<T, SS, M> Controller<T, SS, M>
+where
+    T: Write<u8> + Transfer<u8>,
+    SS: StatefulOutputPin,
+    <SS as OutputPin>::Error: core::fmt::Debug,
+    M: Receiving,
+{
+    pub fn receive(&mut self, fifo_number: u8) -> Result<message::ReceiveMessage, spi::Error> {
+        self.inner.receive(fifo_number)
+    }
+
+    pub fn try_receive(
+        &mut self,
+        fifo_number: u8,
+    ) -> nb::Result<message::ReceiveMessage, spi::Error> {
+        self.inner.try_receive(fifo_number)
+    }
+
+    pub fn try_receive_any(
+        &mut self,
+        fifo_numbers: &[u8],
+    ) -> nb::Result<(u8, message::ReceiveMessage), spi::Error> {
+        self.inner.try_receive_any(fifo_numbers)
+    }
+
+    pub fn try_receive_configured(
+        &mut self,
+    ) -> nb::Result<(u8, message::ReceiveMessage), spi::Error> {
+        self.inner.try_receive_configured()
+    }
+}
@@ -0,0 +1,221 @@
+use crate::can::fifo;
+use crate::generic::{RAM_SIZE, RAM_START_ADDRESS};
+use crate::message;
+use crate::settings;
+
+/// Size of a TEF object without a timestamp (TE0 and TE1).
+const TEF_OBJECT_SIZE: usize = 8;
+/// Extra bytes added to TEF and RX objects when timestamps are enabled.
+const TIMESTAMP_SIZE: usize = 4;
+/// Size of an object in a FIFO left at its reset value: one receive object with an 8 byte
+/// payload and no timestamp.
+const DEFAULT_FIFO_SIZE: usize = message::TX_HEADER_SIZE + 8;
+
+pub enum Error {
+    /// A FIFO configuration used a number outside of 1 to 31.
+    InvalidFIFO(u8),
+    /// The same FIFO number was configured more than once.
+    DuplicateFIFO(u8),
+    /// A FIFO configuration asked for a depth outside of 1 to 32. Holds the FIFO number.
+    InvalidFIFOSize(u8),
+    /// The configuration needs more than the 2K of message RAM. Holds the number of bytes
+    /// that would have been needed.
+    Overflow(usize),
+}
+
+/// Where the TEF, TXQ and FIFOs end up in message RAM for a given configuration.
+///
+/// The controller allocates RAM in a fixed order: TEF, TXQ, then FIFO 1 to 31. The TEF and
+/// TXQ only take space when enabled, but every FIFO takes at least one object, so FIFOs
+/// that aren't configured but sit below a configured one are counted with their reset
+/// size. FIFOs above the highest configured one are left out since nothing uses them.
+pub struct Layout {
+    // Kept as usize so an oversized configuration can be summed up in full before it is
+    // rejected.
+    transmit_event_fifo: Option<usize>,
+    txqueue: Option<usize>,
+    fifos: [Option<usize>; 32],
+    size: usize,
+}
+
+impl Layout {
+    /// Computes the layout, failing if a FIFO number or depth is invalid, if a FIFO number
+    /// is repeated or if the result doesn't fit in message RAM.
+    pub fn plan(
+        transmit_event_fifo: &settings::TransmitEventFIFOConfiguration,
+        txqueue: &settings::TxQueueConfiguration,
+        fifoconfigs: &[settings::FIFOConfiguration],
+    ) -> Result<Layout, Error> {
+        let mut configs: [Option<&settings::FIFOConfiguration>; 32] = [None; 32];
+        for config in fifoconfigs {
+            if !(1..=31).contains(&config.fifo_number) {
+                return Err(Error::InvalidFIFO(config.fifo_number));
+            }
+            if !(1..=32).contains(&config.fifo_size) {
+                return Err(Error::InvalidFIFOSize(config.fifo_number));
+            }
+            let slot = &mut configs[config.fifo_number as usize];
+            if slot.is_some() {
+                return Err(Error::DuplicateFIFO(config.fifo_number));
+            }
+            *slot = Some(config);
+        }
+
+        let mut layout = Layout {
+            transmit_event_fifo: None,
+            txqueue: None,
+            fifos: [None; 32],
+            size: 0,
+        };
+
+        if transmit_event_fifo.fifo_size > 0 {
+            let object_size = if transmit_event_fifo.timestamp {
+                TEF_OBJECT_SIZE + TIMESTAMP_SIZE
+            } else {
+                TEF_OBJECT_SIZE
+            };
+            layout.transmit_event_fifo = Some(layout.next_address());
+            layout.size += object_size * object_count(transmit_event_fifo.fifo_size);
+        }
+
+        if txqueue.fifo_size > 0 {
+            let object_size = message::TX_HEADER_SIZE + txqueue.payload_size.bytes();
+            layout.txqueue = Some(layout.next_address());
+            layout.size += object_size * object_count(txqueue.fifo_size);
+        }
+
+        let highest_fifo = fifoconfigs
+            .iter()
+            .map(|config| config.fifo_number)
+            .max()
+            .unwrap_or(0);
+        for fifo_number in 1..=highest_fifo {
+            layout.fifos[fifo_number as usize] = Some(layout.next_address());
+            layout.size += match configs[fifo_number as usize] {
+                Some(config) => fifo_object_size(config) * object_count(config.fifo_size),
+                None => DEFAULT_FIFO_SIZE,
+            };
+        }
+
+        if layout.size > RAM_SIZE as usize {
+            return Err(Error::Overflow(layout.size));
+        }
+
+        Ok(layout)
+    }
+
+    /// RAM address of the first TEF object, if the TEF is enabled.
+    pub fn transmit_event_fifo_address(&self) -> Option<u16> {
+        self.transmit_event_fifo.map(|address| address as u16)
+    }
+
+    /// RAM address of the first TXQ object, if the TXQ is enabled.
+    pub fn txqueue_address(&self) -> Option<u16> {
+        self.txqueue.map(|address| address as u16)
+    }
+
+    /// RAM address of the first object of FIFO 1 to 31.
+    pub fn fifo_address(&self, fifo_number: u8) -> Option<u16> {
+        match self.fifos.get(fifo_number as usize) {
+            Some(address) => address.map(|address| address as u16),
+            None => None,
+        }
+    }
+
+    /// Number of bytes of message RAM used.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of bytes of message RAM left over.
+    pub fn free(&self) -> usize {
+        RAM_SIZE as usize - self.size
+    }
+
+    fn next_address(&self) -> usize {
+        RAM_START_ADDRESS as usize + self.size
+    }
+}
+
+/// FSIZE holds the number of objects minus one, so a FIFO has 1 to 32 objects.
+fn object_count(fifo_size: u8) -> usize {
+    fifo_size.clamp(1, 32) as usize
+}
+
+fn fifo_object_size(config: &settings::FIFOConfiguration) -> usize {
+    let header_size = match config.mode {
+        fifo::Mode::Transmit => message::TX_HEADER_SIZE,
+        fifo::Mode::Receive => message::ReceiveMessage::header_size(config.timestamp),
+    };
+    header_size + config.payload_size.bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::can;
+
+    fn receive_fifo(fifo_number: u8, fifo_size: u8) -> settings::FIFOConfiguration {
+        settings::FIFOConfiguration {
+            fifo_number,
+            priority: 0,
+            payload_size: can::control::PayloadSize::Bytes64,
+            fifo_size,
+            retry_attempt: can::control::RetransmissionAttempts::Disabled,
+            mode: fifo::Mode::Receive,
+            timestamp: true,
+        }
+    }
+
+    fn disabled() -> (
+        settings::TransmitEventFIFOConfiguration,
+        settings::TxQueueConfiguration,
+    ) {
+        (
+            settings::TransmitEventFIFOConfiguration {
+                fifo_size: 0,
+                timestamp: false,
+            },
+            settings::TxQueueConfiguration {
+                message_priority: 0,
+                retransmission_attempts: can::control::RetransmissionAttempts::Disabled,
+                fifo_size: 0,
+                payload_size: can::control::PayloadSize::Bytes8,
+            },
+        )
+    }
+
+    #[test]
+    fn addresses_follow_each_other() {
+        let (tef, txq) = disabled();
+        let fifos = [receive_fifo(1, 2), receive_fifo(3, 1)];
+        let layout = match Layout::plan(&tef, &txq, &fifos) {
+            Ok(layout) => layout,
+            Err(_) => panic!("layout should fit"),
+        };
+        assert_eq!(layout.fifo_address(1), Some(0x400));
+        assert_eq!(layout.fifo_address(2), Some(0x400 + 2 * 76));
+        assert_eq!(layout.fifo_address(3), Some(0x400 + 2 * 76 + 16));
+        assert_eq!(layout.size(), 2 * 76 + 16 + 76);
+    }
+
+    #[test]
+    fn far_too_large_is_reported_as_overflow() {
+        let (tef, txq) = disabled();
+        let mut fifos: [_; 28] = core::array::from_fn(|index| receive_fifo(index as u8 + 1, 32));
+        fifos[26].fifo_size = 17;
+        match Layout::plan(&tef, &txq, &fifos) {
+            Err(Error::Overflow(size)) => assert_eq!(size, 27 * 32 * 76 + 17 * 76),
+            _ => panic!("expected an overflow"),
+        }
+    }
+
+    #[test]
+    fn zero_depth_is_rejected() {
+        let (tef, txq) = disabled();
+        match Layout::plan(&tef, &txq, &[receive_fifo(1, 0)]) {
+            Err(Error::InvalidFIFOSize(1)) => (),
+            _ => panic!("expected an invalid FIFO size"),
+        }
+    }
+}
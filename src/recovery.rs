@@ -0,0 +1,293 @@
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::{OutputPin, StatefulOutputPin};
+
+use crate::can::bus::BusState;
+use crate::can::control::OperationMode;
+use crate::spi;
+
+pub enum Policy {
+    /// Let the controller rejoin the bus as soon as its own recovery is done.
+    Automatic,
+    /// Keep the controller off the bus for at least this many microseconds from going
+    /// bus-off. If its own recovery finishes sooner it is held in Configuration mode for the
+    /// rest of the time.
+    BackOff(u32),
+    /// Keep the controller off the bus until BusOffRecovery::resume is called, holding it in
+    /// Configuration mode if its own recovery finishes first.
+    Manual,
+}
+
+/// A step of the recovery, as reported by BusOffRecovery::poll.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Transition {
+    /// The node went bus-off.
+    BusOff,
+    /// The node was held in Configuration mode and has been put back in its previous mode.
+    Recovering,
+    /// The node is back to error active. Holds the number of recoveries so far.
+    Recovered(u32),
+}
+
+enum State {
+    BusOn,
+    /// Waiting for the controller to finish its own recovery, with the time since going
+    /// bus-off in microseconds.
+    BusOff(u32),
+    /// Held in Configuration mode, with the time since going bus-off in microseconds.
+    OffBus(u32),
+    WaitingForBus,
+}
+
+/// Watches for bus-off and brings the controller back according to a Policy. Call poll
+/// regularly from the main loop.
+///
+/// The controller always runs the recovery sequence ISO 11898-1 asks for, waiting for 128
+/// occurrences of 11 recessive bits before it clears C1TREC.TXBO. It is left in its mode
+/// until then, since Configuration mode resets the error counters and would cut the
+/// sequence short. Policies that keep the node off the bus for longer only take it to
+/// Configuration mode afterwards, so it may take part in bus traffic until the next poll.
+/// It is then returned to whatever mode it was in when it went bus-off.
+pub struct BusOffRecovery {
+    policy: Policy,
+    state: State,
+    mode: OperationMode,
+    resume_requested: bool,
+    recoveries: u32,
+}
+
+impl BusOffRecovery {
+    pub fn new(policy: Policy) -> Self {
+        BusOffRecovery {
+            policy,
+            state: State::BusOn,
+            mode: OperationMode::NormalCanFD,
+            resume_requested: false,
+            recoveries: 0,
+        }
+    }
+
+    /// Checks the bus state and moves the recovery along. `elapsed_us` is the time since
+    /// the previous call. Returns the transition that happened, if any.
+    pub fn poll<T, SS>(
+        &mut self,
+        controller: &mut spi::Controller<T, SS>,
+        elapsed_us: u32,
+    ) -> Result<Option<Transition>, spi::Error>
+    where
+        T: Write<u8> + Transfer<u8>,
+        SS: StatefulOutputPin,
+        <SS as OutputPin>::Error: core::fmt::Debug,
+    {
+        match self.state {
+            State::BusOn => {
+                if controller.bus_state()?.state != BusState::BusOff {
+                    return Ok(None);
+                }
+
+                self.mode = controller.operation_mode()?;
+                self.resume_requested = false;
+                self.state = State::BusOff(0);
+                Ok(Some(Transition::BusOff))
+            }
+            State::BusOff(elapsed) => {
+                let elapsed = elapsed.saturating_add(elapsed_us);
+                self.state = State::BusOff(elapsed);
+                if controller.bus_state()?.state == BusState::BusOff {
+                    return Ok(None);
+                }
+
+                if self.may_rejoin(elapsed) {
+                    self.state = State::WaitingForBus;
+                    return self.recovered(controller);
+                }
+                controller.request_mode(OperationMode::Configuration)?;
+                self.state = State::OffBus(elapsed);
+                Ok(None)
+            }
+            State::OffBus(elapsed) => {
+                let elapsed = elapsed.saturating_add(elapsed_us);
+                self.state = State::OffBus(elapsed);
+                if !self.may_rejoin(elapsed) {
+                    return Ok(None);
+                }
+
+                controller.request_mode(self.mode)?;
+                self.state = State::WaitingForBus;
+                Ok(Some(Transition::Recovering))
+            }
+            State::WaitingForBus => self.recovered(controller),
+        }
+    }
+
+    /// Whether the policy lets the node back on the bus, `elapsed` microseconds after it
+    /// went bus-off.
+    fn may_rejoin(&self, elapsed: u32) -> bool {
+        match self.policy {
+            Policy::Automatic => true,
+            Policy::BackOff(back_off) => elapsed >= back_off,
+            Policy::Manual => self.resume_requested,
+        }
+    }
+
+    /// Reports the recovery once the node is error active in its previous mode.
+    fn recovered<T, SS>(
+        &mut self,
+        controller: &mut spi::Controller<T, SS>,
+    ) -> Result<Option<Transition>, spi::Error>
+    where
+        T: Write<u8> + Transfer<u8>,
+        SS: StatefulOutputPin,
+        <SS as OutputPin>::Error: core::fmt::Debug,
+    {
+        if controller.bus_state()?.state != BusState::ErrorActive
+            || controller.operation_mode()? != self.mode
+        {
+            return Ok(None);
+        }
+
+        self.recoveries += 1;
+        self.state = State::BusOn;
+        Ok(Some(Transition::Recovered(self.recoveries)))
+    }
+
+    /// Lets the controller rejoin the bus with the manual policy. Has no effect otherwise.
+    pub fn resume(&mut self) {
+        self.resume_requested = true;
+    }
+
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy;
+    }
+
+    /// Number of times the node has recovered from bus-off.
+    pub fn recoveries(&self) -> u32 {
+        self.recoveries
+    }
+
+    /// True from going bus-off until the node is back to error active.
+    pub fn is_recovering(&self) -> bool {
+        !matches!(self.state, State::BusOn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::SFRAddress;
+    use crate::mock;
+
+    fn controller() -> spi::Controller<mock::Device, mock::Pin> {
+        let mut controller = mock::controller(mock::Device::new());
+        controller.request_mode(OperationMode::NormalCan2).unwrap();
+        controller
+    }
+
+    fn set_bus_off(controller: &mut spi::Controller<mock::Device, mock::Pin>, bus_off: bool) {
+        // TXBO along with the TEC it takes to get there.
+        let c1trec = if bus_off { (1 << 21) | (0xFF << 8) } else { 0 };
+        controller.write_sfr(&SFRAddress::C1TREC, c1trec).unwrap();
+        assert_eq!(
+            controller.bus_state().unwrap().state == BusState::BusOff,
+            bus_off
+        );
+    }
+
+    fn mode(controller: &mut spi::Controller<mock::Device, mock::Pin>) -> OperationMode {
+        controller.operation_mode().unwrap()
+    }
+
+    #[test]
+    fn automatic_waits_for_controller_recovery() {
+        let mut controller = controller();
+        let mut recovery = BusOffRecovery::new(Policy::Automatic);
+        assert_eq!(recovery.poll(&mut controller, 100).unwrap(), None);
+
+        set_bus_off(&mut controller, true);
+        assert_eq!(
+            recovery.poll(&mut controller, 100).unwrap(),
+            Some(Transition::BusOff)
+        );
+        assert_eq!(recovery.poll(&mut controller, 100).unwrap(), None);
+        assert!(recovery.is_recovering());
+        assert_eq!(mode(&mut controller), OperationMode::NormalCan2);
+
+        set_bus_off(&mut controller, false);
+        assert_eq!(
+            recovery.poll(&mut controller, 100).unwrap(),
+            Some(Transition::Recovered(1))
+        );
+        assert!(!recovery.is_recovering());
+    }
+
+    #[test]
+    fn back_off_holds_node_after_controller_recovery() {
+        let mut controller = controller();
+        let mut recovery = BusOffRecovery::new(Policy::BackOff(1000));
+
+        set_bus_off(&mut controller, true);
+        assert_eq!(
+            recovery.poll(&mut controller, 0).unwrap(),
+            Some(Transition::BusOff)
+        );
+        // Configuration mode would reset the counters, so the mode is left alone until
+        // the controller is done.
+        assert_eq!(recovery.poll(&mut controller, 300).unwrap(), None);
+        assert_eq!(mode(&mut controller), OperationMode::NormalCan2);
+
+        set_bus_off(&mut controller, false);
+        assert_eq!(recovery.poll(&mut controller, 300).unwrap(), None);
+        assert_eq!(mode(&mut controller), OperationMode::Configuration);
+        assert_eq!(recovery.poll(&mut controller, 300).unwrap(), None);
+
+        assert_eq!(
+            recovery.poll(&mut controller, 400).unwrap(),
+            Some(Transition::Recovering)
+        );
+        assert_eq!(mode(&mut controller), OperationMode::NormalCan2);
+        assert_eq!(
+            recovery.poll(&mut controller, 0).unwrap(),
+            Some(Transition::Recovered(1))
+        );
+    }
+
+    #[test]
+    fn back_off_already_over_rejoins_right_away() {
+        let mut controller = controller();
+        let mut recovery = BusOffRecovery::new(Policy::BackOff(1000));
+
+        set_bus_off(&mut controller, true);
+        recovery.poll(&mut controller, 0).unwrap();
+        assert_eq!(recovery.poll(&mut controller, 2000).unwrap(), None);
+
+        set_bus_off(&mut controller, false);
+        assert_eq!(
+            recovery.poll(&mut controller, 0).unwrap(),
+            Some(Transition::Recovered(1))
+        );
+        assert_eq!(mode(&mut controller), OperationMode::NormalCan2);
+    }
+
+    #[test]
+    fn manual_waits_for_resume() {
+        let mut controller = controller();
+        let mut recovery = BusOffRecovery::new(Policy::Manual);
+
+        set_bus_off(&mut controller, true);
+        recovery.poll(&mut controller, 0).unwrap();
+        set_bus_off(&mut controller, false);
+        assert_eq!(recovery.poll(&mut controller, 0).unwrap(), None);
+        assert_eq!(mode(&mut controller), OperationMode::Configuration);
+        assert_eq!(recovery.poll(&mut controller, 1_000_000).unwrap(), None);
+
+        recovery.resume();
+        assert_eq!(
+            recovery.poll(&mut controller, 0).unwrap(),
+            Some(Transition::Recovering)
+        );
+        assert_eq!(
+            recovery.poll(&mut controller, 0).unwrap(),
+            Some(Transition::Recovered(1))
+        );
+        assert_eq!(recovery.recoveries(), 1);
+    }
+}
@@ -11,10 +11,72 @@ pub enum SysClkDivider {
 }
 
 pub struct Oscillator {
+    /// Frequency of the crystal or clock on OSC1 in Hz
+    pub frequency: u32,
     pub pll: PLL,
     pub divider: SysClkDivider,
 }
 
+impl Oscillator {
+    /// SYSCLK frequency in Hz, after the PLL and divider.
+    pub fn sysclk(&self) -> u32 {
+        let clock = match self.pll {
+            PLL::On => self.frequency * 10,
+            PLL::Off => self.frequency,
+        };
+        match self.divider {
+            SysClkDivider::DivByOne => clock,
+            SysClkDivider::DivByTwo => clock / 2,
+        }
+    }
+}
+
+pub struct BitRate {
+    /// Bits per second
+    pub bitrate: u32,
+    /// Tenths of a percent, e.g. 800 for 80%
+    pub sample_point: u16,
+}
+
+pub enum TransmitterDelayCompensation {
+    Disabled,
+    /// Measure the transmitter delay, needed for data bitrates above 1 Mbit/s
+    Auto,
+    /// Use a fixed transmitter delay in SYSCLK cycles, 0 to 63
+    Manual(u8),
+}
+
+pub enum TimestampCapture {
+    StartOfFrame,
+    EndOfFrame,
+}
+
+pub struct TimestampConfiguration {
+    /// Enables the time base counter
+    pub enabled: bool,
+    /// SYSCLK divider for the time base counter, 1 to 1024
+    pub prescaler: u16,
+    pub capture: TimestampCapture,
+}
+
+/// Interrupt sources that drive the INT pin. FIFO interrupts also need to be enabled in the
+/// FIFO's own control register.
+pub struct InterruptConfiguration {
+    pub transmit: bool,
+    pub receive: bool,
+    pub time_base_counter: bool,
+    pub mode_change: bool,
+    pub transmit_event_fifo: bool,
+    pub ecc: bool,
+    pub spi_crc: bool,
+    pub transmit_attempt: bool,
+    pub receive_overflow: bool,
+    pub system_error: bool,
+    pub bus_error: bool,
+    pub wake_up: bool,
+    pub invalid_message: bool,
+}
+
 pub struct IOConfiguration {
     pub enable_tx_standby_pin: bool,
     pub txcan_open_drain: bool,
@@ -22,6 +84,12 @@ pub struct IOConfiguration {
     pub interrupt_pin_open_drain: bool,
 }
 
+pub struct TransmitEventFIFOConfiguration {
+    /// 0 disables the TEF, otherwise 1 to 32
+    pub fifo_size: u8,
+    pub timestamp: bool,
+}
+
 pub struct TxQueueConfiguration {
     pub message_priority: u8,
     pub retransmission_attempts: can::control::RetransmissionAttempts,
@@ -30,20 +98,36 @@ pub struct TxQueueConfiguration {
 }
 
 pub struct FIFOConfiguration {
-    /// 0 to 31
+    /// 1 to 31
     pub fifo_number: u8,
     /// See can::control::C1TXQCON::highest_priority()
     pub priority: u8,
     pub payload_size: can::control::PayloadSize,
-    /// 0 to 32
+    /// 1 to 32
     pub fifo_size: u8,
     pub retry_attempt: can::control::RetransmissionAttempts,
     pub mode: can::fifo::Mode,
+    /// Only used by receive FIFOs
+    pub timestamp: bool,
 }
 
 pub struct Settings<'a> {
     pub oscillator: Oscillator,
+    /// Bitrate of the arbitration phase, and of the whole frame for CAN 2.0
+    pub nominal_bitrate: BitRate,
+    /// Bitrate of the data phase of CAN FD frames sent with BRS set
+    pub data_bitrate: BitRate,
+    /// The offset is derived from the data bit timing
+    pub transmitter_delay_compensation: TransmitterDelayCompensation,
     pub ioconfiguration: IOConfiguration,
+    pub interrupts: InterruptConfiguration,
+    pub timestamp: TimestampConfiguration,
+    pub transmit_event_fifo: TransmitEventFIFOConfiguration,
     pub txqueue: TxQueueConfiguration,
     pub fifoconfigs: &'a [FIFOConfiguration],
+    /// Number of leading data bits of standard frames compared by filters, 0 to 18. See
+    /// can::filter::DataBits
+    pub device_net_filter_bits: u8,
+    /// Mode entered once configure() is done
+    pub operation_mode: can::control::OperationMode,
 }
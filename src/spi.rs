@@ -1,4 +1,5 @@
 use core::cmp::Ord;
+use core::convert::TryFrom;
 use embedded_hal::blocking::delay::DelayUs;
 use embedded_hal::blocking::spi::{Transfer, Write};
 use embedded_hal::digital::v2::{OutputPin, StatefulOutputPin};
@@ -6,27 +7,87 @@ use embedded_hal::digital::v2::{OutputPin, StatefulOutputPin};
 use crate::can;
 use crate::can::fifo;
 use crate::generic::*;
+use crate::message;
+use crate::ram;
 use crate::settings;
 
+#[derive(Debug)]
 pub enum Error {
     SPIRead,
     SPIWrite,
     InvalidFIFO(u8),
     InvalidRAMAddress(u16),
+    /// The FIFO has no free object to load a message into.
+    FIFOFull(u8),
+    /// The FIFO isn't configured as a transmit FIFO.
+    NotTransmitFIFO(u8),
+    /// The message payload doesn't fit in the FIFO's configured payload size. A received
+    /// message this happens to is dropped.
+    PayloadTooLarge(u8),
+    /// The FIFO has no message waiting to be read.
+    FIFOEmpty(u8),
+    /// The FIFO isn't configured as a receive FIFO.
+    NotReceiveFIFO(u8),
+    /// Messages were dropped because the receive FIFO was full. The flag is cleared, so
+    /// the next receive returns the oldest message still in the FIFO.
+    ReceiveOverflow(u8),
+    InvalidFilter(u8),
+    /// The filter has to be disabled before it can be changed.
+    FilterEnabled(u8),
+    /// OperationMode::Unknown can't be requested.
+    InvalidMode,
+    /// The controller didn't reach the requested mode in time. Holds the mode it was
+    /// still in.
+    ModeChangeTimeout(can::control::OperationMode),
+    /// OSC.OSCRDY didn't come up in time after waking from Sleep.
+    OscillatorNotReady,
     Other,
 }
 
+/// How long configure() waits for each mode change.
+const MODE_CHANGE_TIMEOUT_US: u32 = 2000;
+/// How often set_mode() and wake() poll the controller.
+const MODE_POLL_INTERVAL_US: u32 = 100;
+
 pub enum ConfigError {
     ConfigurationModeTimeout,
     SPIFailedRAMEcho,
     PLLNotReady,
+    /// A FIFO configuration used a number outside of 1 to 31.
+    InvalidFIFO(u8),
+    /// The same FIFO number was configured more than once.
+    DuplicateFIFO(u8),
+    /// A FIFO configuration asked for a depth outside of 1 to 32. Holds the FIFO number.
+    InvalidFIFOSize(u8),
+    /// The nominal bitrate can't be reached from SYSCLK.
+    NominalBitRate,
+    /// The data bitrate can't be reached from SYSCLK.
+    DataBitRate,
+    /// The TEF, TXQ and FIFOs need more than the 2K of message RAM. Holds the number of
+    /// bytes that would have been needed.
+    RAMOverflow(usize),
+    /// The controller didn't enter the mode asked for in Settings. Holds the mode it was
+    /// still in.
+    ModeChangeTimeout(can::control::OperationMode),
     Other(Error),
 }
 
+impl From<ram::Error> for ConfigError {
+    fn from(error: ram::Error) -> Self {
+        match error {
+            ram::Error::InvalidFIFO(fifo_number) => ConfigError::InvalidFIFO(fifo_number),
+            ram::Error::DuplicateFIFO(fifo_number) => ConfigError::DuplicateFIFO(fifo_number),
+            ram::Error::InvalidFIFOSize(fifo_number) => ConfigError::InvalidFIFOSize(fifo_number),
+            ram::Error::Overflow(size) => ConfigError::RAMOverflow(size),
+        }
+    }
+}
+
 impl From<Error> for ConfigError {
     fn from(error: Error) -> Self {
         match error {
             Error::SPIRead | Error::SPIWrite => ConfigError::ConfigurationModeTimeout,
+            Error::ModeChangeTimeout(mode) => ConfigError::ModeChangeTimeout(mode),
             _ => ConfigError::Other(error),
         }
     }
@@ -35,6 +96,16 @@ impl From<Error> for ConfigError {
 pub struct Controller<T, SS> {
     spi_master: T,
     slave_select: SS,
+    /// FIFO the embedded-can traits transmit through, 0 being the TXQ.
+    can_transmit_fifo: u8,
+    /// Bit n is set if FIFO n is polled by the embedded-can traits.
+    can_receive_fifos: u32,
+    /// Bit n is set if FIFO n was last set up as a receive FIFO by configure_fifo_control.
+    receive_fifos: u32,
+    /// Set once set_can_fifos picked the FIFOs, so configure doesn't replace them.
+    can_fifos_selected: bool,
+    /// Mode sleep() left, for wake() to return to.
+    mode_before_sleep: can::control::OperationMode,
 }
 
 impl<T, SS> Controller<T, SS>
@@ -48,6 +119,11 @@ where
         Self {
             spi_master,
             slave_select,
+            can_transmit_fifo: 0,
+            can_receive_fifos: 0,
+            receive_fifos: 0,
+            can_fifos_selected: false,
+            mode_before_sleep: can::control::OperationMode::Configuration,
         }
     }
 
@@ -59,28 +135,40 @@ where
         // I'm going to borrow the ordering and logic for this code from pierremolinaro
         // on github: https://github.com/pierremolinaro/acan2517
 
-        let mut c1con = match self.read_sfr(&SFRAddress::C1CON) {
-            Ok(val) => can::control::C1CON(val),
-            Err(_) => return Err(ConfigError::ConfigurationModeTimeout),
+        // Make sure everything fits in message RAM before touching any registers.
+        ram::Layout::plan(
+            &settings.transmit_event_fifo,
+            &settings.txqueue,
+            settings.fifoconfigs,
+        )?;
+
+        let sysclk = settings.oscillator.sysclk();
+        let nominal_timing = match can::timing::BitTiming::nominal(
+            sysclk,
+            settings.nominal_bitrate.bitrate,
+            settings.nominal_bitrate.sample_point,
+        ) {
+            Ok(timing) => timing,
+            Err(_) => return Err(ConfigError::NominalBitRate),
         };
-        c1con.set_opmode(can::control::OperationMode::Configuration);
-        match self.write_sfr(&SFRAddress::C1CON, c1con.0) {
-            Ok(_) => (),
-            Err(_) => return Err(ConfigError::ConfigurationModeTimeout),
+        let data_timing = match can::timing::BitTiming::data(
+            sysclk,
+            settings.data_bitrate.bitrate,
+            settings.data_bitrate.sample_point,
+        ) {
+            Ok(timing) => timing,
+            Err(_) => return Err(ConfigError::DataBitRate),
         };
 
-        // Delay 2ms checking every 500us for config mode
-        for i in 0..5 {
-            if c1con.opmode() == can::control::OperationMode::Configuration {
-                break;
-            } else if i == 4 {
-                return Err(ConfigError::ConfigurationModeTimeout);
-            }
-            delay.delay_us(500u32);
-            c1con = match self.read_sfr(&SFRAddress::C1CON) {
-                Ok(val) => can::control::C1CON(val),
-                Err(_) => can::control::C1CON(0),
-            };
+        if self
+            .set_mode(
+                can::control::OperationMode::Configuration,
+                delay,
+                MODE_CHANGE_TIMEOUT_US,
+            )
+            .is_err()
+        {
+            return Err(ConfigError::ConfigurationModeTimeout);
         }
 
         // Now in configuration mode --------------------
@@ -103,22 +191,58 @@ where
             osc
         })?;
 
-        match settings.oscillator.pll {
-            settings::PLL::On => {
-                // Wait 1 ms for PLL ready
-                for i in 0..3 {
-                    let osc = OSCRegister(self.read_sfr(&SFRAddress::OSC)?);
-                    if osc.pllrdy() {
-                        break;
-                    } else if i == 3 {
-                        return Err(ConfigError::PLLNotReady);
-                    }
-                    delay.delay_us(500u32);
+        if let settings::PLL::On = settings.oscillator.pll {
+            // Wait 1 ms for PLL ready
+            for i in 0..3 {
+                let osc = OSCRegister(self.read_sfr(&SFRAddress::OSC)?);
+                if osc.pllrdy() {
+                    break;
+                } else if i == 2 {
+                    return Err(ConfigError::PLLNotReady);
                 }
+                delay.delay_us(500u32);
             }
-            _ => (),
         }
 
+        // Setup bit timing ---------------------------------------
+
+        self.modify_sfr(can::timing::C1NBTCFG, |mut c1nbtcfg| {
+            c1nbtcfg.set_timing(&nominal_timing);
+            c1nbtcfg
+        })?;
+        self.modify_sfr(can::timing::C1DBTCFG, |mut c1dbtcfg| {
+            c1dbtcfg.set_timing(&data_timing);
+            c1dbtcfg
+        })?;
+        self.modify_sfr(can::timing::C1TDC, |mut c1tdc| {
+            match settings.transmitter_delay_compensation {
+                settings::TransmitterDelayCompensation::Disabled => {
+                    c1tdc.set_tdcmode(can::timing::TdcMode::Disabled)
+                }
+                settings::TransmitterDelayCompensation::Auto => {
+                    c1tdc.set_tdcmode(can::timing::TdcMode::Auto)
+                }
+                settings::TransmitterDelayCompensation::Manual(value) => {
+                    c1tdc.set_tdcmode(can::timing::TdcMode::Manual);
+                    c1tdc.set_tdcv(value);
+                }
+            }
+            c1tdc.set_tdco(can::timing::transmitter_delay_offset(&data_timing));
+            c1tdc
+        })?;
+
+        // Setup time base counter --------------------------------
+
+        self.modify_sfr(can::timestamp::C1TSCON, |mut c1tscon| {
+            c1tscon.set_tbcen(settings.timestamp.enabled);
+            c1tscon.set_prescaler(settings.timestamp.prescaler);
+            c1tscon.set_tseof(match settings.timestamp.capture {
+                settings::TimestampCapture::StartOfFrame => false,
+                settings::TimestampCapture::EndOfFrame => true,
+            });
+            c1tscon
+        })?;
+
         // Setup IOCON -------------------------------------------
 
         self.modify_sfr(IOCONRegister, |mut iocon| {
@@ -129,6 +253,37 @@ where
             iocon
         })?;
 
+        // Setup interrupts ---------------------------------------
+
+        let interrupts = &settings.interrupts;
+        self.modify_sfr(can::interrupt::C1INT, |mut c1int| {
+            c1int.set_txie(interrupts.transmit);
+            c1int.set_rxie(interrupts.receive);
+            c1int.set_tbcie(interrupts.time_base_counter);
+            c1int.set_modie(interrupts.mode_change);
+            c1int.set_tefie(interrupts.transmit_event_fifo);
+            c1int.set_eccie(interrupts.ecc);
+            c1int.set_spicrcie(interrupts.spi_crc);
+            c1int.set_txatie(interrupts.transmit_attempt);
+            c1int.set_rxovie(interrupts.receive_overflow);
+            c1int.set_serrie(interrupts.system_error);
+            c1int.set_cerrie(interrupts.bus_error);
+            c1int.set_wakie(interrupts.wake_up);
+            c1int.set_ivmie(interrupts.invalid_message);
+            c1int
+        })?;
+
+        // Setup Transmit Event FIFO ----------------------------
+
+        let uses_tef = settings.transmit_event_fifo.fifo_size > 0;
+        if uses_tef {
+            self.modify_sfr(can::control::C1TEFCON, |mut c1tefcon| {
+                c1tefcon.set_fifo_size(settings.transmit_event_fifo.fifo_size);
+                c1tefcon.set_teftsen(settings.transmit_event_fifo.timestamp);
+                c1tefcon
+            })?;
+        }
+
         // Setup Transmission Queue ------------------------------
 
         let uses_txq = settings.txqueue.fifo_size > 0;
@@ -142,21 +297,57 @@ where
             c1txqcon
         })?;
 
-        if uses_txq {
-            let mut c1con = can::control::C1CON(self.read_sfr(&SFRAddress::C1CON)?);
-            if c1con.txqen() != uses_txq {
-                // Disable TXQ and TEF if we don't want to store transmit queue
-                c1con.set_txqen(uses_txq);
-                c1con.set_stef(false);
-                self.write_sfr(&SFRAddress::C1CON, c1con.into())?;
+        // Both are enabled out of reset, turn off whichever isn't wanted so it doesn't take
+        // up RAM.
+        self.modify_sfr(can::control::C1CON, |mut c1con| {
+            c1con.set_txqen(uses_txq);
+            c1con.set_stef(uses_tef);
+            c1con.set_dncnt(core::cmp::min(
+                settings.device_net_filter_bits,
+                can::filter::MAX_DATA_BITS,
+            ));
+            c1con
+        })?;
+
+        // Setup FIFOs -------------------------------------------
+
+        let mut transmit_fifo = if uses_txq { Some(0) } else { None };
+        let mut receive_fifos: u32 = 0;
+        for config in settings.fifoconfigs {
+            self.configure_fifo_control(config.fifo_number, |control| {
+                control.set_txen(config.mode == fifo::Mode::Transmit);
+                control.set_txpri(config.priority);
+                control.set_txat(config.retry_attempt.into());
+                // FSIZE holds the number of objects minus one, the planner checked it's
+                // 1 to 32 objects.
+                control.set_fsize(config.fifo_size - 1);
+                control.set_plsize(config.payload_size.into());
+                control.set_rxtsen(config.mode == fifo::Mode::Receive && config.timestamp);
+                control
+            })?;
+
+            match config.mode {
+                fifo::Mode::Transmit => {
+                    transmit_fifo.get_or_insert(config.fifo_number);
+                }
+                fifo::Mode::Receive => receive_fifos |= 1 << config.fifo_number,
             }
         }
 
+        // Let the embedded-can traits use what was just configured, unless set_can_fifos
+        // already picked something.
+        if !self.can_fifos_selected {
+            self.can_transmit_fifo = transmit_fifo.unwrap_or(0);
+            self.can_receive_fifos = receive_fifos;
+        }
+
+        self.set_mode(settings.operation_mode, delay, MODE_CHANGE_TIMEOUT_US)?;
+
         Ok(())
     }
 
     /// Ready slave select will pull the slave select line to ACTIVE.
-    fn ready_slave_select(&mut self) -> () {
+    fn ready_slave_select(&mut self) {
         if self.slave_select.is_set_low().unwrap() {
             self.slave_select.set_high().unwrap();
         }
@@ -196,27 +387,231 @@ where
     }
 
     /// Enables the transmit event FIFO by setting C1CON.STEF and C1TEFCON.FSIZE bits.
-    /// Be aware that object_count MUST be <= 31, any other values will be disregarded.
+    /// Be aware that object_count MUST be between 1 and 32, other values will be clamped.
     ///
-    /// Also please keep in mind that the total RAM size is 2K and this code does absolutely
-    /// zero validation that your configuration is under this limit. The documentation recommends
-    /// configuring the TEF first, then TEQ, then FIFOs as necessary.
-    pub fn enable_transmit_event_fifo(&mut self, object_count: u32) -> Result<(), Error> {
-        let mut c1con = self.read_sfr(&SFRAddress::C1CON)?;
+    /// Also please keep in mind that the total RAM size is 2K and this function doesn't check
+    /// that your configuration is under this limit; configure() does so with ram::Layout.
+    /// The documentation recommends configuring the TEF first, then TEQ, then FIFOs as
+    /// necessary.
+    pub fn enable_transmit_event_fifo(&mut self, object_count: u8) -> Result<(), Error> {
+        self.modify_sfr(can::control::C1CON, |mut c1con| {
+            c1con.set_stef(true);
+            c1con
+        })?;
+
+        self.modify_sfr(can::control::C1TEFCON, |mut c1tefcon| {
+            c1tefcon.set_fifo_size(object_count);
+            c1tefcon
+        })
+    }
+
+    /// Reads the transmitter delay measured by the controller, in SYSCLK cycles. Only
+    /// meaningful with transmitter delay compensation in auto mode.
+    pub fn transmitter_delay_value(&mut self) -> Result<u8, Error> {
+        Ok(can::timing::C1TDC(self.read_sfr(&SFRAddress::C1TDC)?).tdcv())
+    }
+
+    /// Reads the current value of the time base counter.
+    pub fn read_time_base_counter(&mut self) -> Result<u32, Error> {
+        Ok(can::timestamp::C1TBC(self.read_sfr(&SFRAddress::C1TBC)?).tbc())
+    }
+
+    /// Helper method for modifying the C1TSCON register.
+    pub fn modify_timestamp_control<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(can::timestamp::C1TSCON) -> can::timestamp::C1TSCON,
+    {
+        self.modify_sfr(can::timestamp::C1TSCON, f)
+    }
+
+    pub fn read_interrupts(&mut self) -> Result<can::interrupt::C1INT, Error> {
+        Ok(can::interrupt::C1INT(self.read_sfr(&SFRAddress::C1INT)?))
+    }
+
+    /// Helper method for modifying the C1INT register. Clearable flags are cleared by
+    /// setting them to false.
+    pub fn modify_interrupts<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(can::interrupt::C1INT) -> can::interrupt::C1INT,
+    {
+        self.modify_sfr(can::interrupt::C1INT, f)
+    }
+
+    /// Reads all of the interrupt flag registers and returns the pending events. Receive
+    /// overflow and transmit attempt flags are cleared in their FIFO, and the clearable C1INT
+    /// flags are cleared; the rest are cleared by servicing the FIFOs themselves.
+    ///
+    /// Meant to be called from the INT pin handler.
+    pub fn service_interrupts(&mut self) -> Result<can::interrupt::Events, Error> {
+        let c1int = can::interrupt::C1INT(self.read_sfr(&SFRAddress::C1INT)?);
+        let c1rxif = self.read_sfr(&SFRAddress::C1RXIF)?;
+        let c1txif = self.read_sfr(&SFRAddress::C1TXIF)?;
+        let c1rxovif = self.read_sfr(&SFRAddress::C1RXOVIF)?;
+        let c1txatif = self.read_sfr(&SFRAddress::C1TXATIF)?;
+
+        let mut rxovif = fifo::StatusRegister(0);
+        rxovif.set_rxovif(true);
+        let mut txatif = fifo::StatusRegister(0);
+        txatif.set_txatif(true);
+        for fifo_number in 0..=31 {
+            if c1rxovif & (1 << fifo_number) != 0 {
+                self.clear_fifo_status_flags(fifo_number, rxovif.0)?;
+            }
+            if c1txatif & (1 << fifo_number) != 0 {
+                self.clear_fifo_status_flags(fifo_number, txatif.0)?;
+            }
+        }
+
+        let handled = c1int.0 & can::interrupt::C1INT::clearable_flags();
+        if handled != 0 {
+            self.write_sfr(&SFRAddress::C1INT, c1int.clear_value(handled))?;
+        }
+
+        Ok(can::interrupt::Events::new(
+            c1int, c1rxif, c1txif, c1rxovif, c1txatif,
+        ))
+    }
+
+    /// Reads the mode the controller is currently in from C1CON.OPMOD.
+    pub fn operation_mode(&mut self) -> Result<can::control::OperationMode, Error> {
+        Ok(can::control::C1CON(self.read_sfr(&SFRAddress::C1CON)?).opmode())
+    }
 
-        // Enable TEF
-        c1con |= 1 << 19;
-        self.write_sfr(&SFRAddress::C1CON, c1con)?;
+    /// Requests a mode change through C1CON.REQOP without waiting for it to happen.
+    pub fn request_mode(&mut self, mode: can::control::OperationMode) -> Result<(), Error> {
+        self.modify_sfr(can::control::C1CON, |mut c1con| {
+            c1con.set_opmode(mode);
+            c1con
+        })
+    }
 
-        let mut c1tefcon = self.read_sfr(&SFRAddress::C1TEFCON)?;
+    /// Requests a mode change and waits up to `timeout_us` microseconds for C1CON.OPMOD to
+    /// confirm it. Leaving Normal or Listen Only mode waits for the bus to go idle, and
+    /// entering a mode that uses the bus waits for 11 recessive bits, so the timeout should
+    /// allow for a whole frame at the nominal bitrate.
+    pub fn set_mode<D: DelayUs<u32>>(
+        &mut self,
+        mode: can::control::OperationMode,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<(), Error> {
+        if mode == can::control::OperationMode::Unknown {
+            return Err(Error::InvalidMode);
+        }
 
-        // Reserve space in RAM, max 31 objects
-        c1tefcon |= object_count & 0b1111;
-        self.write_sfr(&SFRAddress::C1TEFCON, c1tefcon)?;
+        self.request_mode(mode)?;
 
+        let mut waited = 0;
+        loop {
+            let current = self.operation_mode()?;
+            if current == mode {
+                return Ok(());
+            } else if waited >= timeout_us {
+                return Err(Error::ModeChangeTimeout(current));
+            }
+            delay.delay_us(MODE_POLL_INTERVAL_US);
+            waited = waited.saturating_add(MODE_POLL_INTERVAL_US);
+        }
+    }
+
+    /// Sets up waking from Sleep on bus activity. `filter` turns on the RXCAN wake-up
+    /// filter, which ignores glitches shorter than the given time, and `interrupt` sets
+    /// C1INT.WAKIE so that a wake-up is reported on the INT pin. Without the interrupt
+    /// the controller only wakes through SPI. C1CON can only be changed in Configuration
+    /// mode.
+    pub fn configure_wakeup(
+        &mut self,
+        filter: Option<can::control::WakeupFilterTime>,
+        interrupt: bool,
+    ) -> Result<(), Error> {
+        self.modify_sfr(can::control::C1CON, |mut c1con| {
+            match filter {
+                Some(time) => {
+                    c1con.set_wakfil(true);
+                    c1con.set_wft(time);
+                }
+                None => c1con.set_wakfil(false),
+            }
+            c1con
+        })?;
+        self.modify_sfr(can::interrupt::C1INT, |mut c1int| {
+            c1int.set_wakie(interrupt);
+            can::interrupt::C1INT(c1int.clear_value(0))
+        })
+    }
+
+    /// Puts the controller in Sleep mode, remembering the mode it was in for wake().
+    pub fn sleep<D: DelayUs<u32>>(&mut self, delay: &mut D, timeout_us: u32) -> Result<(), Error> {
+        let mode = self.operation_mode()?;
+        if mode == can::control::OperationMode::Sleep {
+            return Ok(());
+        }
+        self.set_mode(can::control::OperationMode::Sleep, delay, timeout_us)?;
+        self.mode_before_sleep = mode;
         Ok(())
     }
 
+    /// Wakes the controller up and returns it to the mode it was in before sleep(). Reading
+    /// a register doesn't wake it, so OSC.OSCDIS is cleared first; this is harmless if bus
+    /// activity already woke it. Waits up to `timeout_us` microseconds for the oscillator to
+    /// be ready and for each mode change, and clears the wake-up interrupt flag.
+    pub fn wake<D: DelayUs<u32>>(&mut self, delay: &mut D, timeout_us: u32) -> Result<(), Error> {
+        self.modify_osc(|mut osc| {
+            osc.set_oscdis(false);
+            osc
+        })?;
+
+        let mut waited = 0;
+        loop {
+            let osc = OSCRegister(self.read_sfr(&SFRAddress::OSC)?);
+            if osc.oscrdy() {
+                break;
+            } else if waited >= timeout_us {
+                return Err(Error::OscillatorNotReady);
+            }
+            delay.delay_us(MODE_POLL_INTERVAL_US);
+            waited = waited.saturating_add(MODE_POLL_INTERVAL_US);
+        }
+
+        // The controller comes out of Sleep in Configuration mode, which is also the only
+        // way into the other modes.
+        self.set_mode(
+            can::control::OperationMode::Configuration,
+            delay,
+            timeout_us,
+        )?;
+        let c1int = can::interrupt::C1INT(self.read_sfr(&SFRAddress::C1INT)?);
+        let mut wakif = can::interrupt::C1INT(0);
+        wakif.set_wakif(true);
+        self.write_sfr(&SFRAddress::C1INT, c1int.clear_value(wakif.0))?;
+        self.set_mode(self.mode_before_sleep, delay, timeout_us)
+    }
+
+    /// Reads C1TREC to get the fault confinement state and both error counters.
+    pub fn bus_state(&mut self) -> Result<can::bus::BusStatus, Error> {
+        let c1trec = can::bus::C1TREC(self.read_sfr(&SFRAddress::C1TREC)?);
+        Ok(c1trec.into())
+    }
+
+    /// Reads C1BDIAG0 and C1BDIAG1 to see which kinds of bus errors have happened.
+    pub fn diagnostics(&mut self) -> Result<can::bus::Diagnostics, Error> {
+        Ok(can::bus::Diagnostics {
+            counters: can::bus::C1BDIAG0(self.read_sfr(&SFRAddress::C1BDIAG0)?),
+            flags: can::bus::C1BDIAG1(self.read_sfr(&SFRAddress::C1BDIAG1)?),
+        })
+    }
+
+    /// Resets the diagnostic counters and error flags.
+    pub fn clear_diagnostics(&mut self) -> Result<(), Error> {
+        self.write_sfr(&SFRAddress::C1BDIAG0, 0)?;
+        self.write_sfr(&SFRAddress::C1BDIAG1, 0)
+    }
+
+    /// Reads C1VEC to find the highest priority pending interrupt.
+    pub fn read_interrupt_vector(&mut self) -> Result<can::interrupt::C1VEC, Error> {
+        Ok(can::interrupt::C1VEC(self.read_sfr(&SFRAddress::C1VEC)?))
+    }
+
     /// Configures a FIFO based on the settings provided. As per documentation, a single FIFO must
     /// be dedicated to RX or TX and all objects in that queue must have the same payload size.
     ///
@@ -234,8 +629,16 @@ where
         let raw_register: u32 = self.read_sfr(&address)?;
 
         let mut control_register = fifo::ControlRegister(raw_register);
-
-        self.write_sfr(&address, f(&mut control_register).0)
+        let value = f(&mut control_register).0;
+        self.write_sfr(&address, value)?;
+
+        // Remember the receive FIFOs for try_receive_configured.
+        if control_register.txen() {
+            self.receive_fifos &= !(1 << fifo_number);
+        } else {
+            self.receive_fifos |= 1 << fifo_number;
+        }
+        Ok(())
     }
 
     pub fn read_fifo_status(&mut self, fifo_number: u8) -> Result<fifo::StatusRegister, Error> {
@@ -254,10 +657,7 @@ where
     where
         F: FnOnce(&mut fifo::StatusRegister) -> &mut fifo::StatusRegister,
     {
-        let mut status_register = match self.read_fifo_status(fifo_number) {
-            Ok(reg) => reg,
-            Err(err) => return Err(err),
-        };
+        let mut status_register = self.read_fifo_status(fifo_number)?;
 
         let address = match fifo::get_fifo_status_address(fifo_number) {
             Ok(val) => val,
@@ -267,11 +667,24 @@ where
         self.write_sfr(&address, f(&mut status_register).0)
     }
 
+    /// Clears the given flags in the status register of a FIFO, 0 being the TXQ. The other
+    /// flags are written as 1, which leaves them alone.
+    fn clear_fifo_status_flags(&mut self, fifo_number: u8, flags: u32) -> Result<(), Error> {
+        let address = match fifo_number {
+            0 => SFRAddress::C1TXQSTA,
+            _ => match fifo::get_fifo_status_address(fifo_number) {
+                Ok(addr) => addr,
+                Err(e) => return Err(Error::InvalidFIFO(e)),
+            },
+        };
+        self.write_sfr(&address, fifo::StatusRegister::clearable_flags() & !flags)
+    }
+
     pub fn read_fifo_user_address(
         &mut self,
         fifo_number: u8,
     ) -> Result<fifo::UserAddressRegister, Error> {
-        let address = match fifo::get_fifo_status_address(fifo_number) {
+        let address = match fifo::get_fifo_user_address_address(fifo_number) {
             Ok(val) => val,
             Err(e) => return Err(Error::InvalidFIFO(e)),
         };
@@ -286,7 +699,7 @@ where
     where
         F: FnOnce(&mut fifo::UserAddressRegister) -> fifo::UserAddressRegister,
     {
-        let address = match fifo::get_fifo_status_address(fifo_number) {
+        let address = match fifo::get_fifo_user_address_address(fifo_number) {
             Ok(val) => val,
             Err(e) => return Err(Error::InvalidFIFO(e)),
         };
@@ -299,6 +712,276 @@ where
         self.write_sfr(&address, f(&mut register).0)
     }
 
+    /// Programs acceptance filter 0 to 31 with an identifier, mask and destination FIFO. The
+    /// filter is left disabled; call enable_filter once it is set up.
+    ///
+    /// Returns Error::FilterEnabled if the filter is currently enabled, as the controller
+    /// requires filters to be disabled while they are modified.
+    pub fn set_filter(
+        &mut self,
+        filter_number: u8,
+        filter: &can::filter::Filter,
+    ) -> Result<(), Error> {
+        let (control_address, object_address, mask_address) =
+            Self::filter_addresses(filter_number)?;
+        if !(1..=31).contains(&filter.fifo) {
+            return Err(Error::InvalidFIFO(filter.fifo));
+        }
+
+        let mut control = can::filter::ControlRegister(self.read_sfr(&control_address)?);
+        if control.enabled(filter_number) {
+            return Err(Error::FilterEnabled(filter_number));
+        }
+
+        self.write_sfr(&object_address, filter.object_register().into())?;
+        self.write_sfr(&mask_address, filter.mask_register().into())?;
+
+        control.set_fifo(filter_number, filter.fifo);
+        self.write_sfr(&control_address, control.into())
+    }
+
+    pub fn enable_filter(&mut self, filter_number: u8) -> Result<(), Error> {
+        self.set_filter_enabled(filter_number, true)
+    }
+
+    pub fn disable_filter(&mut self, filter_number: u8) -> Result<(), Error> {
+        self.set_filter_enabled(filter_number, false)
+    }
+
+    fn set_filter_enabled(&mut self, filter_number: u8, enabled: bool) -> Result<(), Error> {
+        let address = match can::filter::get_filter_control_address(filter_number) {
+            Ok(addr) => addr,
+            Err(e) => return Err(Error::InvalidFilter(e)),
+        };
+
+        let mut control = can::filter::ControlRegister(self.read_sfr(&address)?);
+        control.set_enabled(filter_number, enabled);
+        self.write_sfr(&address, control.into())
+    }
+
+    /// Programs the filters worked out by can::filter::compile. Every filter is disabled
+    /// first, then the compiled ones are written and enabled; the rest stay disabled.
+    pub fn apply_filters(&mut self, compiled: &can::filter::CompiledFilters) -> Result<(), Error> {
+        for (filter_number, filter) in compiled.filters().iter().enumerate() {
+            let filter_number = filter_number as u8;
+            self.disable_filter(filter_number)?;
+            if let Some(filter) = filter {
+                self.set_filter(filter_number, filter)?;
+                self.enable_filter(filter_number)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up the control, object and mask registers for filter 0 to 31.
+    fn filter_addresses(filter_number: u8) -> Result<(SFRAddress, SFRAddress, SFRAddress), Error> {
+        match (
+            can::filter::get_filter_control_address(filter_number),
+            can::filter::get_filter_object_address(filter_number),
+            can::filter::get_mask_address(filter_number),
+        ) {
+            (Ok(control), Ok(object), Ok(mask)) => Ok((control, object, mask)),
+            _ => Err(Error::InvalidFilter(filter_number)),
+        }
+    }
+
+    /// Loads a message into the TXQ (fifo_number 0) or a transmit FIFO (1 to 31) and requests
+    /// its transmission.
+    ///
+    /// Returns Error::FIFOFull if there is no free object in the queue and
+    /// Error::NotTransmitFIFO if the FIFO is configured for receive.
+    pub fn transmit(
+        &mut self,
+        fifo_number: u8,
+        message: &message::TransmitMessage,
+    ) -> Result<(), Error> {
+        let (control_address, status_address, user_address) = if fifo_number == 0 {
+            (
+                SFRAddress::C1TXQCON,
+                SFRAddress::C1TXQSTA,
+                SFRAddress::C1TXQUA,
+            )
+        } else {
+            Self::fifo_addresses(fifo_number)?
+        };
+
+        // C1TXQCON and C1TXQSTA share their bit layout with the FIFO registers for
+        // everything used here.
+        let mut control = fifo::ControlRegister(self.read_sfr(&control_address)?);
+        if !control.txen() {
+            return Err(Error::NotTransmitFIFO(fifo_number));
+        }
+
+        let payload_size = match can::control::PayloadSize::try_from(control.plsize()) {
+            Ok(size) => size.bytes(),
+            Err(_) => return Err(Error::Other),
+        };
+        if message.length() > payload_size {
+            return Err(Error::PayloadTooLarge(fifo_number));
+        }
+
+        let status = fifo::StatusRegister(self.read_sfr(&status_address)?);
+        if !status.tfnrfnif() {
+            return Err(Error::FIFOFull(fifo_number));
+        }
+
+        let user_address = fifo::UserAddressRegister(self.read_sfr(&user_address)?);
+        let (length, bytes) = message.bytes();
+        self.write_ram(
+            RAM_START_ADDRESS + user_address.fifoua() as u16,
+            &bytes[..length],
+        )?;
+
+        // Increment the FIFO head and request transmission in one write.
+        control.set_uinc(true);
+        control.set_txreq(true);
+        self.write_sfr(&control_address, control.into())
+    }
+
+    /// Reads the oldest message out of a receive FIFO (1 to 31) and advances the FIFO tail.
+    ///
+    /// Returns Error::FIFOEmpty if there is nothing to read and Error::NotReceiveFIFO if the
+    /// FIFO is configured for transmit. If messages were dropped since the last read this
+    /// returns Error::ReceiveOverflow once before going on with the messages that are left.
+    pub fn receive(&mut self, fifo_number: u8) -> Result<message::ReceiveMessage, Error> {
+        let (control_address, status_address, user_address) = Self::fifo_addresses(fifo_number)?;
+
+        let mut control = fifo::ControlRegister(self.read_sfr(&control_address)?);
+        if control.txen() {
+            return Err(Error::NotReceiveFIFO(fifo_number));
+        }
+
+        let status = fifo::StatusRegister(self.read_sfr(&status_address)?);
+        if status.rxovif() {
+            let mut rxovif = fifo::StatusRegister(0);
+            rxovif.set_rxovif(true);
+            self.clear_fifo_status_flags(fifo_number, rxovif.0)?;
+            return Err(Error::ReceiveOverflow(fifo_number));
+        }
+        if !status.tfnrfnif() {
+            return Err(Error::FIFOEmpty(fifo_number));
+        }
+
+        let payload_size = match can::control::PayloadSize::try_from(control.plsize()) {
+            Ok(size) => size.bytes(),
+            Err(_) => return Err(Error::Other),
+        };
+        let object_size = message::ReceiveMessage::header_size(control.rxtsen()) + payload_size;
+
+        let user_address = fifo::UserAddressRegister(self.read_sfr(&user_address)?);
+        let mut buffer = [0u8; message::RX_HEADER_SIZE + message::MAX_BUFFER_SIZE];
+        self.read_ram(
+            RAM_START_ADDRESS + user_address.fifoua() as u16,
+            &mut buffer[..object_size],
+        )?;
+
+        let message = message::ReceiveMessage::from_bytes(&buffer[..object_size], control.rxtsen());
+
+        // Tell the controller we're done with this object. This has to happen even if it
+        // couldn't be parsed, or the FIFO would be stuck on it.
+        control.set_uinc(true);
+        self.write_sfr(&control_address, control.into())?;
+
+        // The controller only stores as much of the payload as PLSIZE allows, so a frame
+        // whose DLC asks for more can't be read back.
+        message.map_err(|_| Error::PayloadTooLarge(fifo_number))
+    }
+
+    /// Non-blocking version of transmit. Returns WouldBlock while the FIFO is full.
+    pub fn try_transmit(
+        &mut self,
+        fifo_number: u8,
+        message: &message::TransmitMessage,
+    ) -> nb::Result<(), Error> {
+        match self.transmit(fifo_number, message) {
+            Ok(()) => Ok(()),
+            Err(Error::FIFOFull(_)) => Err(nb::Error::WouldBlock),
+            Err(err) => Err(nb::Error::Other(err)),
+        }
+    }
+
+    /// Non-blocking version of receive. Returns WouldBlock while the FIFO is empty.
+    pub fn try_receive(&mut self, fifo_number: u8) -> nb::Result<message::ReceiveMessage, Error> {
+        match self.receive(fifo_number) {
+            Ok(message) => Ok(message),
+            Err(Error::FIFOEmpty(_)) => Err(nb::Error::WouldBlock),
+            Err(err) => Err(nb::Error::Other(err)),
+        }
+    }
+
+    /// Polls each of the given FIFOs in the order they are listed, so put the most
+    /// important first, and returns the first message found along with the FIFO it came
+    /// from. FIFOs that aren't set up for receive are skipped. Returns WouldBlock if all of
+    /// them are empty.
+    pub fn try_receive_any(
+        &mut self,
+        fifo_numbers: &[u8],
+    ) -> nb::Result<(u8, message::ReceiveMessage), Error> {
+        for &fifo_number in fifo_numbers {
+            match self.try_receive(fifo_number) {
+                Ok(message) => return Ok((fifo_number, message)),
+                Err(nb::Error::WouldBlock) | Err(nb::Error::Other(Error::NotReceiveFIFO(_))) => {
+                    continue
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(nb::Error::WouldBlock)
+    }
+
+    /// Polls every FIFO set up for receive through configure_fifo_control. Receive FIFOs
+    /// have no priority of their own, so they are polled lowest number first. Returns
+    /// WouldBlock if all of them are empty.
+    pub fn try_receive_configured(&mut self) -> nb::Result<(u8, message::ReceiveMessage), Error> {
+        let fifos = self.receive_fifos;
+        self.try_receive_fifos(fifos)
+    }
+
+    /// Polls the FIFOs whose bit is set in `fifos`, lowest number first.
+    fn try_receive_fifos(
+        &mut self,
+        fifos: u32,
+    ) -> nb::Result<(u8, message::ReceiveMessage), Error> {
+        for fifo_number in 1..=31 {
+            if fifos & (1 << fifo_number) == 0 {
+                continue;
+            }
+            match self.try_receive(fifo_number) {
+                Ok(message) => return Ok((fifo_number, message)),
+                Err(nb::Error::WouldBlock) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(nb::Error::WouldBlock)
+    }
+
+    /// Picks the FIFOs used by the embedded-can trait implementations. Until this is
+    /// called, configure picks the TXQ or else the first transmit FIFO, and every receive
+    /// FIFO it set up. Receive FIFOs are polled lowest number first; invalid numbers are
+    /// ignored.
+    pub fn set_can_fifos(&mut self, transmit_fifo: u8, receive_fifos: &[u8]) {
+        self.can_fifos_selected = true;
+        self.can_transmit_fifo = transmit_fifo;
+        self.can_receive_fifos = 0;
+        for &fifo_number in receive_fifos {
+            if (1..=31).contains(&fifo_number) {
+                self.can_receive_fifos |= 1 << fifo_number;
+            }
+        }
+    }
+
+    /// Looks up the control, status and user address registers for FIFO 1 to 31.
+    fn fifo_addresses(fifo_number: u8) -> Result<(SFRAddress, SFRAddress, SFRAddress), Error> {
+        match (
+            fifo::get_fifo_control_address(fifo_number),
+            fifo::get_fifo_status_address(fifo_number),
+            fifo::get_fifo_user_address_address(fifo_number),
+        ) {
+            (Ok(control), Ok(status), Ok(user)) => Ok((control, status, user)),
+            _ => Err(Error::InvalidFIFO(fifo_number)),
+        }
+    }
+
     /// Verify SPI connection is working by writing to an available ram location.
     pub fn verify_spi_communications(&mut self) -> Result<(), ConfigError> {
         let address = 0x400;
@@ -383,15 +1066,15 @@ where
     }
 
     fn verify_ram_address(&self, address: u16, data_size: usize) -> Result<(), Error> {
-        let low_address = 0x400;
-        let high_address = 0xBFF;
+        let low_address = RAM_START_ADDRESS;
+        let high_address = RAM_START_ADDRESS + RAM_SIZE;
 
         if address < low_address {
             return Err(Error::InvalidRAMAddress(address));
         }
 
         match (address + data_size as u16).cmp(&high_address) {
-            core::cmp::Ordering::Greater => return Err(Error::InvalidRAMAddress(address)),
+            core::cmp::Ordering::Greater => Err(Error::InvalidRAMAddress(address)),
             _ => Ok(()),
         }
     }
@@ -441,3 +1124,144 @@ where
         (self.spi_master, self.slave_select)
     }
 }
+
+impl embedded_can::Error for Error {
+    /// Bit, stuff, CRC, form and acknowledge errors are handled by the controller itself
+    /// and only show up in Controller::diagnostics(), never as a failed transmit or
+    /// receive. Apart from overruns the errors here are about SPI, FIFO setup or the
+    /// arguments given, which have no matching ErrorKind.
+    fn kind(&self) -> embedded_can::ErrorKind {
+        match self {
+            Error::ReceiveOverflow(_) => embedded_can::ErrorKind::Overrun,
+            _ => embedded_can::ErrorKind::Other,
+        }
+    }
+}
+
+impl<T, SS> embedded_can::nb::Can for Controller<T, SS>
+where
+    T: Write<u8> + Transfer<u8>,
+    SS: StatefulOutputPin,
+    <SS as OutputPin>::Error: core::fmt::Debug,
+{
+    type Frame = message::TransmitMessage;
+    type Error = Error;
+
+    /// Queues the frame in the FIFO picked with set_can_fifos. Pending frames are never
+    /// replaced, so this always returns Ok(None) on success.
+    fn transmit(&mut self, frame: &Self::Frame) -> nb::Result<Option<Self::Frame>, Self::Error> {
+        let fifo_number = self.can_transmit_fifo;
+        self.try_transmit(fifo_number, frame).map(|_| None)
+    }
+
+    /// Returns the first frame found in the FIFOs picked with set_can_fifos.
+    fn receive(&mut self) -> nb::Result<Self::Frame, Self::Error> {
+        let fifos = self.can_receive_fifos;
+        self.try_receive_fifos(fifos)
+            .map(|(_, message)| message.into())
+    }
+}
+
+impl<T, SS> embedded_can::blocking::Can for Controller<T, SS>
+where
+    T: Write<u8> + Transfer<u8>,
+    SS: StatefulOutputPin,
+    <SS as OutputPin>::Error: core::fmt::Debug,
+{
+    type Frame = message::TransmitMessage;
+    type Error = Error;
+
+    fn transmit(&mut self, frame: &Self::Frame) -> Result<(), Self::Error> {
+        nb::block!(embedded_can::nb::Can::transmit(self, frame)).map(|_| ())
+    }
+
+    fn receive(&mut self) -> Result<Self::Frame, Self::Error> {
+        nb::block!(embedded_can::nb::Can::receive(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    /// Sets up FIFO 1 as a receive FIFO with 8 byte payloads and no timestamps, holding one
+    /// object at the start of RAM.
+    fn device_with_object(object: &[u8]) -> mock::Device {
+        let mut device = mock::Device::new();
+        let mut control = fifo::ControlRegister(0);
+        control.set_plsize(can::control::PayloadSize::Bytes8.into());
+        device.set_sfr(SFRAddress::C1FIFOCON1, control.into());
+        let mut status = fifo::StatusRegister(0);
+        status.set_tfnrfnif(true);
+        device.set_sfr(SFRAddress::C1FIFOSTA1, status.0);
+        device.ram(RAM_START_ADDRESS)[..object.len()].copy_from_slice(object);
+        device
+    }
+
+    fn uinc_set(device: &mock::Device) -> bool {
+        fifo::ControlRegister(device.sfr(SFRAddress::C1FIFOCON1)).uinc()
+    }
+
+    #[test]
+    fn receive_reads_object_and_increments_fifo() {
+        let object = [
+            0x23, 0x01, 0, 0, 0x03, 0, 0, 0, 0xAA, 0xBB, 0xCC, 0, 0, 0, 0, 0,
+        ];
+        let mut controller = mock::controller(device_with_object(&object));
+
+        let message = match controller.receive(1) {
+            Ok(message) => message,
+            Err(_) => panic!("the object should be readable"),
+        };
+        assert_eq!(message.data(), &[0xAA, 0xBB, 0xCC]);
+
+        let (device, _) = controller.free();
+        assert!(uinc_set(&device));
+    }
+
+    #[test]
+    fn oversized_object_is_dropped_instead_of_blocking_fifo() {
+        // A CAN FD frame with 64 bytes of data in a FIFO that only keeps 8.
+        let object = [0x23, 0x01, 0, 0, 0x8F, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8];
+        let mut controller = mock::controller(device_with_object(&object));
+
+        match controller.receive(1) {
+            Err(Error::PayloadTooLarge(1)) => (),
+            _ => panic!("expected the payload to be too large"),
+        }
+
+        let (device, _) = controller.free();
+        assert!(uinc_set(&device));
+    }
+
+    #[test]
+    fn wake_enables_oscillator_and_clears_only_wakif() {
+        let mut device = mock::Device::new();
+        let mut c1int = can::interrupt::C1INT(0);
+        c1int.set_wakif(true);
+        c1int.set_tbcif(true);
+        c1int.set_wakie(true);
+        device.set_sfr(SFRAddress::C1INT, c1int.0);
+
+        let mut controller = mock::controller(device);
+        let mut delay = mock::Delay;
+        controller
+            .set_mode(can::control::OperationMode::NormalCan2, &mut delay, 0)
+            .unwrap();
+        controller.sleep(&mut delay, 0).unwrap();
+        assert!(OSCRegister(controller.read_sfr(&SFRAddress::OSC).unwrap()).oscdis());
+
+        controller.wake(&mut delay, 0).unwrap();
+
+        assert!(!OSCRegister(controller.read_sfr(&SFRAddress::OSC).unwrap()).oscdis());
+        assert_eq!(
+            controller.operation_mode().unwrap(),
+            can::control::OperationMode::NormalCan2
+        );
+        let c1int = can::interrupt::C1INT(controller.read_sfr(&SFRAddress::C1INT).unwrap());
+        assert!(!c1int.wakif());
+        assert!(c1int.tbcif());
+        assert!(c1int.wakie());
+    }
+}